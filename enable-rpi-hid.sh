@@ -10,6 +10,8 @@ PRODUCT="Bridged USB Keyboard"
 SERIAL_NUMBER="1a2b3c4deee10213abc"
 LANGUAGE="0x409"  # English (US) (https://web.archive.org/web/20000816171911/http://usb.org/developers/data/USB_LANGIDs.pdf)
 VENDOR_ID="0x1d6b" # Linux Foundation
+REPORT_MODE="boot" # "boot" for the 8 byte boot protocol report (6 keys), or "nkro"
+                   # for N-key rollover. Must match `gadget.report_mode`.
 # End configuration   =========================================================
 
 if [ "${EUID}" -ne 0 ]; then
@@ -41,8 +43,8 @@ FUNCTIONS_DIR="functions/hid.usb0"
 mkdir -p "$FUNCTIONS_DIR"
 echo 1 > "${FUNCTIONS_DIR}/protocol" # Keyboard
 echo 0 > "${FUNCTIONS_DIR}/subclass" # No subclass
-echo 8 > "${FUNCTIONS_DIR}/report_length"
-echo -ne `# Spoof as keyboard (https://www.kernel.org/doc/html/latest/usb/gadget_hid.html)`\
+# The modifiers, the reserved byte and the LEDs are the same in both report modes
+KEYBOARD_REPORT_DESC=`# Spoof as keyboard (https://www.kernel.org/doc/html/latest/usb/gadget_hid.html)`\
 \\0x05\\0x01`# USAGE_PAGE (Generic Desktop)`\
 \\0x09\\0x06`# USAGE (Keyboard)`\
 \\0xa1\\0x01`# COLLECTION (Application)`\
@@ -65,17 +67,70 @@ echo -ne `# Spoof as keyboard (https://www.kernel.org/doc/html/latest/usb/gadget
 \\0x91\\0x02`# OUTPUT (Data,Var,Abs)`\
 \\0x95\\0x01`# REPORT_COUNT (1)`\
 \\0x75\\0x03`# REPORT_SIZE (3)`\
-\\0x91\\0x03`# OUTPUT (Cnst,Var,Abs)`\
+\\0x91\\0x03`# OUTPUT (Cnst,Var,Abs)`
+if [ "${REPORT_MODE}" = "nkro" ]; then
+    # [modifiers, reserved, a bit for each key from 0x00 to 0xDF (28 bytes)]
+    echo 30 > "${FUNCTIONS_DIR}/report_length"
+    KEYS_REPORT_DESC=\
+\\0x05\\0x07`# USAGE_PAGE (Keyboard)`\
+\\0x19\\0x00`# USAGE_MINIMUM (Reserved)`\
+\\0x29\\0xdf`# USAGE_MAXIMUM (0xDF)`\
+\\0x15\\0x00`# LOGICAL_MINIMUM (0)`\
+\\0x25\\0x01`# LOGICAL_MAXIMUM (1)`\
+\\0x75\\0x01`# REPORT_SIZE (1)`\
+\\0x95\\0xe0`# REPORT_COUNT (224)`\
+\\0x81\\0x02`# INPUT (Data,Var,Abs)`
+else
+    # [modifiers, reserved, key 1, ..., key 6]
+    echo 8 > "${FUNCTIONS_DIR}/report_length"
+    KEYS_REPORT_DESC=\
 \\0x95\\0x06`# REPORT_COUNT (6)`\
 \\0x75\\0x08`# REPORT_SIZE (8)`\
 \\0x15\\0x00`# LOGICAL_MINIMUM (0)`\
-\\0x25\\0x65`# LOGICAL_MAXIMUM (101)`\
+\\0x26\\0xdd\\0x00`# LOGICAL_MAXIMUM (221)`\
 \\0x05\\0x07`# USAGE_PAGE (Keyboard)`\
 \\0x19\\0x00`# USAGE_MINIMUM (Reserved)`\
-\\0x29\\0x65`# USAGE_MAXIMUM (Keyboard Application)`\
-\\0x81\\0x00`# INPUT (Data,Ary,Abs)`\
+\\0x29\\0xdd`# USAGE_MAXIMUM (Keypad Hexadecimal)`\
+\\0x81\\0x00`# INPUT (Data,Ary,Abs)`
+fi
+echo -ne "${KEYBOARD_REPORT_DESC}${KEYS_REPORT_DESC}"\
 \\0xc0`#     # END_COLLECTION`\
 > "${FUNCTIONS_DIR}/report_desc"
+# Setup the media and system keys as a second HID function, with one report per usage page
+CONTROL_FUNCTIONS_DIR="functions/hid.usb1"
+mkdir -p "$CONTROL_FUNCTIONS_DIR"
+echo 0 > "${CONTROL_FUNCTIONS_DIR}/protocol" # None
+echo 0 > "${CONTROL_FUNCTIONS_DIR}/subclass" # No subclass
+echo 3 > "${CONTROL_FUNCTIONS_DIR}/report_length" # The longest: [report ID, usage (16 bit)]
+echo -ne `# Consumer control (media keys), report ID 1`\
+\\0x05\\0x0c`# USAGE_PAGE (Consumer Devices)`\
+\\0x09\\0x01`# USAGE (Consumer Control)`\
+\\0xa1\\0x01`# COLLECTION (Application)`\
+\\0x85\\0x01`# REPORT_ID (1)`\
+\\0x15\\0x00`# LOGICAL_MINIMUM (0)`\
+\\0x26\\0xff\\0x03`# LOGICAL_MAXIMUM (1023)`\
+\\0x19\\0x00`# USAGE_MINIMUM (Unassigned)`\
+\\0x2a\\0xff\\0x03`# USAGE_MAXIMUM (1023)`\
+\\0x75\\0x10`# REPORT_SIZE (16)`\
+\\0x95\\0x01`# REPORT_COUNT (1)`\
+\\0x81\\0x00`# INPUT (Data,Ary,Abs)`\
+\\0xc0`#     # END_COLLECTION`\
+`# System control (power down, sleep, wake up), report ID 2`\
+\\0x05\\0x01`# USAGE_PAGE (Generic Desktop)`\
+\\0x09\\0x80`# USAGE (System Control)`\
+\\0xa1\\0x01`# COLLECTION (Application)`\
+\\0x85\\0x02`# REPORT_ID (2)`\
+\\0x19\\0x81`# USAGE_MINIMUM (System Power Down)`\
+\\0x29\\0x83`# USAGE_MAXIMUM (System Wake Up)`\
+\\0x15\\0x00`# LOGICAL_MINIMUM (0)`\
+\\0x25\\0x01`# LOGICAL_MAXIMUM (1)`\
+\\0x75\\0x01`# REPORT_SIZE (1)`\
+\\0x95\\0x03`# REPORT_COUNT (3)`\
+\\0x81\\0x02`# INPUT (Data,Var,Abs)`\
+\\0x95\\0x05`# REPORT_COUNT (5)`\
+\\0x81\\0x03`# INPUT (Cnst,Var,Abs)`\
+\\0xc0`#     # END_COLLECTION`\
+> "${CONTROL_FUNCTIONS_DIR}/report_desc"
 # Setup configurations
 CONFIG_INDEX=1
 CONFIGS_DIR="configs/c.${CONFIG_INDEX}"
@@ -85,9 +140,11 @@ CONFIGS_STRINGS_DIR="${CONFIGS_DIR}/strings/${LANGUAGE}"
 mkdir -p "${CONFIGS_STRINGS_DIR}"
 echo "Config ${CONFIG_INDEX}: USB keyboard bridge" > "${CONFIGS_STRINGS_DIR}/configuration"
 ln -s "${FUNCTIONS_DIR}" "${CONFIGS_DIR}/"
+ln -s "${CONTROL_FUNCTIONS_DIR}" "${CONFIGS_DIR}/"
 ls /sys/class/udc > UDC
 
 popd # from /sys/kernel/config/usb_gadget/g1
 popd # from /sys/kernel/config/usb_gadget/
 
-chmod 777 /dev/hidg0  # rwxrwxrwx:root
\ No newline at end of file
+chmod 777 /dev/hidg0  # rwxrwxrwx:root
+chmod 777 /dev/hidg1  # rwxrwxrwx:root
\ No newline at end of file
@@ -4,60 +4,778 @@
 **/
 
 /***** Setup *****/
-use crate::{key::*, Keyboard};
-use log::error;
-use std::process::exit;
+use crate::{config::ConfigKeys, key::*, pattern::*, Keyboard};
+use log::{info, trace, warn};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    process::Command,
+    time::{self, Instant},
+};
 use KeyCode::*;
-use ModifierKey::*;
 use RegularKey::*;
 // Constants
-pub type ChordSequence = [KeyCode];
+pub type ChordSequence = [KeyPattern];
+/// The profile chords without one are active in, and the one active at startup
+pub const DEFAULT_PROFILE: &str = "default";
+const DEFAULT_COMMAND_TIMEOUT_MS: u64 = 10_000_u64;
 
 /***** Chord sequences *****/
 /* A chord sequence begins with the CHORD_SEQUENCE_START_KEY. Once that key has
- * been pressed, the all chords declared in ALL_CHORDS are listened for. However,
- * the start key should not be included as the first element to the array.
+ * been pressed, all chords the keyboard was created with are listened for.
+ * However, the start key should not be included as the first element to the array.
+ * These are the defaults; the configuration file's `[chords]` section overrides them
+ * and its `[[chords.chord]]` tables add more chords.
 **/
 pub const CHORD_SEQUENCE_START_KEY: KeyCode = Regular(Enter);
-pub const ALL_CHORDS: &[&ChordSequence] = &[
-    QUIT_CHORD_SEQUENCE,
-    // Extra chords go here. Example:
-    /* HELLO_WORLD_CHORD_SEQUENCE, */
-];
 pub const QUIT_CHORD_SEQUENCE: &ChordSequence = &[
-    Modifier(EitherShift),
-    Regular(Grave),
-    Regular(Period),
-    Regular(Backspace),
-    Regular(Backspace),
-    Regular(Backspace),
-];
-// Extra chords go here. Example:
-/*
-pub const HELLO_WORLD_CHORD_SEQUENCE: &ChordSequence = &[
-    Modifier(EitherShift),
-    Regular(Grave),
-    Regular(Period),
-    Regular(H), Regular(E), Regular(L), Regular(L), Regular(O),
-    Regular(Space),
-    Regular(W), Regular(O), Regular(R), Regular(L), Regular(D),
+    KeyPattern::EitherSide(ModifierKind::Shift),
+    KeyPattern::Key(Regular(Grave)),
+    KeyPattern::Key(Regular(Period)),
+    KeyPattern::Key(Regular(Backspace)),
+    KeyPattern::Key(Regular(Backspace)),
+    KeyPattern::Key(Regular(Backspace)),
 ];
-*/
+
+/***** Enums *****/
+/// What typing a chord does, as written in the configuration file
+/// (`action = "quit"`, `action = { type = "Hello, World!" }`)
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ChordAction {
+    /// Exit the bridge
+    Quit,
+    /// Log a message
+    Log(String),
+    /// Type text on the host, as with a US layout
+    Type(String),
+    /// Run a shell command on the Pi, killing it after the timeout. Its output
+    /// is logged.
+    Run {
+        command: String,
+        #[serde(default = "default_command_timeout_ms")]
+        timeout_ms: u64,
+    },
+    /// Stop or start sending keys to the host. Chords are still listened for.
+    TogglePassthrough,
+    /// Switch to another profile's chords
+    SwitchProfile(String),
+    /// Reload the chords from the configuration file
+    ReloadConfig,
+    /// Release every key on the host, until pressed again
+    ReleaseAll,
+    /// Press keys together on the host, then release them (e.g. `<Esc>` or
+    /// `<LCtrl> c`)
+    Tap(ConfigKeys),
+}
+
+/// What a key press is to the combos
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ComboPress {
+    /// The key isn't held for a combo. The keys held so far weren't a combo
+    /// either, and are to be pressed first.
+    Pass(Vec<KeyCode>),
+    /// The key is held, as part of a combo that may still be pressed
+    Held,
+    /// The key completed the combo with this index, whose keys are suppressed
+    Fired(usize),
+}
+
+/***** Structs *****/
+/// A chord sequence (without the start key) and what typing it does
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Chord {
+    pub sequence: Vec<KeyPattern>,
+    pub action: ChordAction,
+    /// The profile the chord is active in, or every profile if none
+    pub profile: Option<String>,
+    /// Whether the chord's keys are kept from the host while it may be typed
+    pub consume: bool,
+}
+
+/// A chord typed to completion
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FiredChord {
+    /// Its index in the chords listened for
+    pub chord_idx: usize,
+    /// Whether the key fed isn't part of the chord but ended it, being the
+    /// start key or not continuing a longer chord
+    pub ended_by_key: bool,
+}
+
+/// A trie of the chords listened for, following the keys typed after the
+/// start key. As patterns overlap, every node the keys lead to is followed
+/// at once. A chord that is the start of a longer one fires once the next key
+/// doesn't continue it or the timeout passes. Of the chords the keys complete
+/// together, the one with exact keys first wins, then the first listed.
+pub struct ChordMatcher {
+    start_key: KeyCode,
+    /// How long to wait for the next key of a chord
+    timeout: Duration,
+    /// The root (index 0) is where the start key leads
+    nodes: Vec<ChordNode>,
+    /// The nodes the keys typed so far lead to, the most exact first. Empty
+    /// when not listening for chords.
+    current: Vec<usize>,
+    /// When the keys typed so far are given up on, or fire their chord
+    deadline: Option<Instant>,
+}
+#[derive(Default)]
+struct ChordNode {
+    children: HashMap<KeyCode, usize>,
+    /// The children of patterns other than exact keys
+    pattern_children: Vec<(KeyPattern, usize)>,
+    /// The index of the chord the keys leading here complete
+    chord: Option<usize>,
+    /// Whether a chord consuming its keys is here or further down
+    consumes: bool,
+}
+impl ChordNode {
+    /// The children a key typed leads to, its exact key's first
+    fn children(&self, key_code: KeyCode) -> impl Iterator<Item = usize> + '_ {
+        (self.children.get(&key_code).copied()).into_iter().chain(
+            (self.pattern_children.iter())
+                .filter(move |(pattern, _)| pattern.matches(key_code))
+                .map(|(_, child)| *child),
+        )
+    }
+
+    /// The child a pattern was added as
+    fn pattern_child(&self, pattern: &KeyPattern) -> Option<usize> {
+        match pattern.key() {
+            Some(key_code) => self.children.get(&key_code).copied(),
+            None => (self.pattern_children.iter())
+                .find(|(child_pattern, _)| child_pattern == pattern)
+                .map(|(_, child)| *child),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty() && self.pattern_children.is_empty()
+    }
+}
+impl ChordMatcher {
+    pub fn new(start_key: KeyCode, timeout: Duration) -> Self {
+        Self {
+            start_key,
+            timeout,
+            nodes: vec![ChordNode::default()],
+            current: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    /// Listen for the chords without a profile or with this one, forgetting
+    /// any keys typed so far
+    pub fn set_chords(&mut self, chords: &[Chord], profile: &str) {
+        self.nodes = vec![ChordNode::default()];
+        let active_chords =
+            (chords.iter().enumerate()).filter(|(_, chord)| in_profile(&chord.profile, profile));
+        for (chord_idx, chord) in active_chords {
+            let mut node = 0_usize;
+            self.nodes[node].consumes |= chord.consume;
+            for pattern in &chord.sequence {
+                node = match self.nodes[node].pattern_child(pattern) {
+                    Some(child) => child,
+                    None => {
+                        self.nodes.push(ChordNode::default());
+                        let child = self.nodes.len() - 1;
+                        match pattern.key() {
+                            Some(key_code) => {
+                                self.nodes[node].children.insert(key_code, child);
+                            }
+                            None => {
+                                (self.nodes[node].pattern_children).push((pattern.clone(), child))
+                            }
+                        }
+                        child
+                    }
+                };
+                self.nodes[node].consumes |= chord.consume;
+            }
+            self.nodes[node].chord = Some(chord_idx);
+        }
+        self.reset();
+    }
+
+    /// Stop listening for chords until the start key is typed
+    pub fn reset(&mut self) {
+        self.current.clear();
+        self.deadline = None;
+    }
+
+    pub fn start_key(&self) -> KeyCode {
+        self.start_key
+    }
+
+    /// When `expire` is to be called, while a chord is being typed
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Whether the next keys may be part of a chord consuming its keys
+    pub fn withholds(&self) -> bool {
+        (self.current.iter()).any(|node| self.nodes[*node].consumes)
+    }
+
+    /// Follow a pressed key, returning the chord it fires, if any
+    pub fn feed(&mut self, key_code: KeyCode) -> Option<FiredChord> {
+        let ended = |chord_idx| FiredChord {
+            chord_idx,
+            ended_by_key: true,
+        };
+        if key_code == self.start_key {
+            trace!("Chord sequence start key received. Listening for chords.");
+            let pending_chord = self.chord(&self.current);
+            self.advance(vec![0]);
+            return pending_chord.map(ended);
+        }
+        if self.current.is_empty() {
+            return None;
+        }
+        let mut next = Vec::new();
+        for node in &self.current {
+            for child in self.nodes[*node].children(key_code) {
+                if !next.contains(&child) {
+                    next.push(child);
+                }
+            }
+        }
+        if next.is_empty() {
+            trace!("No chord continues with {key_code:?}");
+            let pending_chord = self.chord(&self.current);
+            self.reset();
+            return pending_chord.map(ended);
+        }
+        if next.iter().all(|node| self.nodes[*node].is_leaf()) {
+            trace!("Chord completed with {key_code:?}");
+            self.reset();
+            return self.chord(&next).map(|chord_idx| FiredChord {
+                chord_idx,
+                ended_by_key: false,
+            });
+        }
+        trace!("Chord continued with {key_code:?}");
+        self.advance(next);
+        None
+    }
+
+    /// Give up on the keys typed so far once the deadline passed, returning
+    /// the chord they complete, if any
+    pub fn expire(&mut self) -> Option<FiredChord> {
+        if self.current.is_empty() {
+            return None;
+        }
+        trace!("Chord timed out");
+        let pending_chord = self.chord(&self.current);
+        self.reset();
+        pending_chord.map(|chord_idx| FiredChord {
+            chord_idx,
+            ended_by_key: false,
+        })
+    }
+
+    /// The chord listened for that fires instead of this one whenever this
+    /// one's keys are typed, if any: one as long whose patterns match every
+    /// key this one's do, and that wins when both are complete
+    pub fn hiding_chord(&self, chords: &[Chord], chord_idx: usize) -> Option<usize> {
+        let sequence = &chords[chord_idx].sequence;
+        let ranks = self.ranks(sequence)?;
+        (self.nodes.iter())
+            .filter_map(|node| node.chord)
+            .filter(|other_idx| *other_idx != chord_idx)
+            .find(|other_idx| {
+                let other = &chords[*other_idx].sequence;
+                other.len() == sequence.len()
+                    && (other.iter().zip(sequence)).all(|(other, pattern)| other.includes(pattern))
+                    && self
+                        .ranks(other)
+                        .is_some_and(|other_ranks| other_ranks < ranks)
+            })
+    }
+
+    /// Where each node leading to a chord comes among the nodes followed at
+    /// once (see `ChordNode::children`), if the chord is listened for
+    fn ranks(&self, sequence: &[KeyPattern]) -> Option<Vec<usize>> {
+        let mut node = 0_usize;
+        (sequence.iter())
+            .map(|pattern| {
+                let child = self.nodes[node].pattern_child(pattern)?;
+                let rank = match pattern.key() {
+                    Some(_) => 0_usize,
+                    None => {
+                        (self.nodes[node].pattern_children.iter())
+                            .position(|(_, pattern_child)| *pattern_child == child)?
+                            + 1
+                    }
+                };
+                node = child;
+                Some(rank)
+            })
+            .collect()
+    }
+
+    /// The chord the first of these nodes completing one completes
+    fn chord(&self, nodes: &[usize]) -> Option<usize> {
+        (nodes.iter()).find_map(|node| self.nodes[*node].chord)
+    }
+
+    fn advance(&mut self, nodes: Vec<usize>) {
+        self.current = nodes;
+        self.deadline = Some(Instant::now() + self.timeout);
+    }
+}
+
+/// Keys pressed together (within a window of time) to do an action
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Combo {
+    pub keys: Vec<KeyPattern>,
+    pub action: ChordAction,
+    /// The profile the combo is active in, or every profile if none
+    pub profile: Option<String>,
+}
+
+/// Holds the presses of keys that may be part of a combo until the combo
+/// is complete, another key is pressed, one is released or the window passes
+pub struct ComboMatcher {
+    /// How long after the first key the others must be pressed
+    window: Duration,
+    /// The active combos' indices and keys
+    combos: Vec<(usize, Vec<KeyPattern>)>,
+    /// The presses held, in order
+    held: Vec<KeyCode>,
+    /// When the presses held are let through
+    deadline: Option<Instant>,
+}
+impl ComboMatcher {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            combos: Vec::new(),
+            held: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    /// Look for the combos without a profile or with this one
+    pub fn set_combos(&mut self, combos: &[Combo], profile: &str) {
+        self.combos = combos
+            .iter()
+            .enumerate()
+            .filter(|(_, combo)| in_profile(&combo.profile, profile))
+            .map(|(combo_idx, combo)| (combo_idx, combo.keys.clone()))
+            .collect();
+    }
+
+    /// When `expire` is to be called, while presses are held
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn press(&mut self, key_code: KeyCode) -> ComboPress {
+        let mut pressed = self.held.clone();
+        pressed.push(key_code);
+        if let Some((combo_idx, _)) = (self.combos.iter())
+            .find(|(_, keys)| keys.len() == pressed.len() && covers(keys, &pressed))
+        {
+            trace!("Combo completed with {key_code:?}");
+            self.held.clear();
+            self.deadline = None;
+            return ComboPress::Fired(*combo_idx);
+        }
+        if (self.combos.iter())
+            .any(|(_, keys)| keys.len() > pressed.len() && covers(keys, &pressed))
+        {
+            if self.held.is_empty() {
+                self.deadline = Some(Instant::now() + self.window);
+            }
+            self.held = pressed;
+            return ComboPress::Held;
+        }
+        self.deadline = None;
+        ComboPress::Pass(std::mem::take(&mut self.held))
+    }
+
+    /// Let the presses held through if a key held is released, returning them
+    pub fn release(&mut self, key_code: KeyCode) -> Vec<KeyCode> {
+        if !self.held.contains(&key_code) {
+            return Vec::new();
+        }
+        self.expire()
+    }
+
+    /// Let the presses held through, returning them
+    pub fn expire(&mut self) -> Vec<KeyCode> {
+        self.deadline = None;
+        std::mem::take(&mut self.held)
+    }
+}
+
+/// Keys held together long enough to quit, as an escape hatch that doesn't
+/// touch the host: the press of the key completing the hold is kept from the
+/// host, and only sent if the hold is let go of early. The hold is timed by
+/// the input events' timestamps.
+pub struct HoldToQuit {
+    /// Empty disables holding to quit
+    keys: Vec<KeyPattern>,
+    duration: Duration,
+    /// When the last of the keys was pressed, while they're all held
+    since: Option<SystemTime>,
+    /// The press of the key completing the hold, while withheld
+    withheld: Option<KeyCode>,
+}
+impl HoldToQuit {
+    pub fn new(keys: Vec<KeyPattern>, duration: Duration) -> Self {
+        Self {
+            keys,
+            duration,
+            since: None,
+            withheld: None,
+        }
+    }
+
+    pub fn keys(&self) -> &[KeyPattern] {
+        &self.keys
+    }
+
+    /// Note whether the keys are all held as of an input event's timestamp
+    pub fn update(&mut self, all_held: bool, timestamp: SystemTime) {
+        match (all_held && !self.keys.is_empty(), self.since) {
+            (true, None) => {
+                trace!("Holding to quit for {:?}", self.duration);
+                self.since = Some(timestamp);
+            }
+            (true, Some(_)) => {}
+            (false, _) => self.since = None,
+        }
+    }
+
+    /// Whether to keep a key's press from the host, as it completes the hold
+    pub fn withhold(&mut self, key_code: KeyCode, all_held: bool) -> bool {
+        let completes = all_held && self.keys.iter().any(|pattern| pattern.matches(key_code));
+        if completes {
+            trace!("Withholding {key_code:?} while holding to quit");
+            self.withheld = Some(key_code);
+        }
+        completes
+    }
+
+    pub fn withheld(&self) -> Option<KeyCode> {
+        self.withheld
+    }
+
+    /// Stop withholding the press, returning it to be sent
+    pub fn let_through(&mut self) -> Option<KeyCode> {
+        self.withheld.take()
+    }
+
+    /// When the keys will have been held long enough, while they're all held
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.since.map(|since| since + self.duration)
+    }
+
+    /// Whether the keys have been held long enough as of a timestamp
+    pub fn is_due(&self, now: SystemTime) -> bool {
+        self.deadline().is_some_and(|deadline| now >= deadline)
+    }
+}
 
 /***** Chord sequence handlers *****/
-impl<'a> Keyboard<'a> {
-    pub fn handle_chord(&mut self, chord: &ChordSequence) {
-        match chord {
-            QUIT_CHORD_SEQUENCE => {
-                exit(0);
+impl Keyboard {
+    pub fn handle_chord(&mut self, action: &ChordAction) {
+        match action {
+            ChordAction::Quit => self.quit_requested = true,
+            ChordAction::Log(message) => info!("{message}"),
+            ChordAction::Type(text) => self.type_text(text),
+            ChordAction::Run {
+                command,
+                timeout_ms,
+            } => {
+                tokio::spawn(run_command(
+                    command.clone(),
+                    Duration::from_millis(*timeout_ms),
+                ));
+            }
+            ChordAction::TogglePassthrough => self.set_passthrough(!self.passthrough),
+            ChordAction::SwitchProfile(profile) => {
+                info!("Switched to the {profile} profile.");
+                self.profile = profile.clone();
+                self.chord_matcher.set_chords(&self.chords, &self.profile);
+                self.combo_matcher.set_combos(&self.combos, &self.profile);
             }
-            // Extra chords go here. Example:
-            /*
-            HELLO_WORLD_CHORD_SEQUENCE => {
-                info!("Hello, World!");
+            ChordAction::ReloadConfig => self.reload_requested = true,
+            ChordAction::ReleaseAll => {
+                info!("Releasing every key.");
+                self.release_all();
             }
-            */
-            _ => error!("Unhandled chord: {chord:?}"),
+            ChordAction::Tap(keys) => self.tap_keys(&keys.0),
         }
     }
 }
+
+/***** Auxiliary functions *****/
+
+/// Whether a chord or combo with a profile (or none, for every profile) is
+/// active in the active profile
+pub fn in_profile(profile: &Option<String>, active: &str) -> bool {
+    (profile.as_ref()).is_none_or(|profile| profile == active)
+}
+
+/// Whether chords or combos with these profiles may be active together
+pub fn same_profile(profile: &Option<String>, other: &Option<String>) -> bool {
+    match (profile, other) {
+        (Some(profile), Some(other)) => profile == other,
+        _ => true,
+    }
+}
+
+/// Whether every key pressed matches a different pattern of a combo
+pub fn covers(patterns: &[KeyPattern], pressed: &[KeyCode]) -> bool {
+    let mut unmatched = patterns.to_vec();
+    pressed.iter().all(|key_code| {
+        // The key itself first, so other patterns are left for other keys
+        let idx = (unmatched.iter())
+            .position(|pattern| pattern.key() == Some(*key_code))
+            .or_else(|| {
+                unmatched
+                    .iter()
+                    .position(|pattern| pattern.matches(*key_code))
+            });
+        idx.map(|idx| unmatched.remove(idx)).is_some()
+    })
+}
+
+fn default_command_timeout_ms() -> u64 {
+    DEFAULT_COMMAND_TIMEOUT_MS
+}
+
+/// Run a chord's shell command, logging its output
+async fn run_command(command: String, timeout: Duration) {
+    info!("Running `{command}`.");
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match time::timeout(timeout, output).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            warn!("Failed to run `{command}`: {e}");
+            return;
+        }
+        Err(_elapsed) => {
+            warn!("`{command}` took longer than {timeout:?}, killed it.");
+            return;
+        }
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        info!("`{command}`: {line}");
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        warn!("`{command}`: {line}");
+    }
+    match output.status.success() {
+        true => info!("`{command}` exited successfully."),
+        false => warn!("`{command}` failed ({}).", output.status),
+    }
+}
+
+/***** Tests *****/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ModifierKey::*;
+
+    fn matcher(sequences: &[&str]) -> ChordMatcher {
+        let chords = sequences
+            .iter()
+            .map(|sequence| Chord {
+                sequence: sequence.parse::<PatternSequence>().unwrap().0,
+                action: ChordAction::Quit,
+                profile: None,
+                consume: false,
+            })
+            .collect::<Vec<_>>();
+        let mut matcher = ChordMatcher::new(Regular(Enter), Duration::from_secs(1));
+        matcher.set_chords(&chords, DEFAULT_PROFILE);
+        matcher
+    }
+
+    fn completed(chord_idx: usize) -> Option<FiredChord> {
+        Some(FiredChord {
+            chord_idx,
+            ended_by_key: false,
+        })
+    }
+
+    fn ended(chord_idx: usize) -> Option<FiredChord> {
+        Some(FiredChord {
+            chord_idx,
+            ended_by_key: true,
+        })
+    }
+
+    /// A chord that is the start of another fires on the next key or timeout
+    #[test]
+    fn prefix_chords() {
+        let mut matcher = matcher(&["a", "a b", "c"]);
+        assert_eq!(matcher.feed(Regular(A)), None, "not listening yet");
+        assert_eq!(matcher.feed(Regular(Enter)), None);
+        assert_eq!(matcher.feed(Regular(A)), None);
+        assert_eq!(matcher.feed(Regular(B)), completed(1));
+        assert_eq!(matcher.deadline(), None);
+
+        matcher.feed(Regular(Enter));
+        matcher.feed(Regular(A));
+        assert_eq!(matcher.feed(Regular(C)), ended(0));
+        assert_eq!(matcher.feed(Regular(C)), None, "listening again");
+
+        matcher.feed(Regular(Enter));
+        matcher.feed(Regular(A));
+        assert!(matcher.deadline().is_some());
+        assert_eq!(matcher.expire(), completed(0));
+        assert_eq!(matcher.expire(), None);
+
+        matcher.feed(Regular(Enter));
+        matcher.feed(Regular(A));
+        assert_eq!(matcher.feed(Regular(Enter)), ended(0), "restarted");
+        assert_eq!(matcher.feed(Modifier(RightShift)), None);
+        assert_eq!(matcher.feed(Regular(C)), None);
+    }
+
+    /// Keys are withheld only while a consuming chord may still be typed
+    #[test]
+    fn consuming_chords() {
+        let chords = [("a b", true), ("c d", false)].map(|(sequence, consume)| Chord {
+            sequence: sequence.parse::<PatternSequence>().unwrap().0,
+            action: ChordAction::Quit,
+            profile: None,
+            consume,
+        });
+        let mut matcher = ChordMatcher::new(Regular(Enter), Duration::from_secs(1));
+        matcher.set_chords(&chords, DEFAULT_PROFILE);
+        assert!(!matcher.withholds());
+        matcher.feed(Regular(Enter));
+        assert!(matcher.withholds());
+        matcher.feed(Regular(A));
+        assert!(matcher.withholds());
+        matcher.feed(Regular(Enter));
+        matcher.feed(Regular(C));
+        assert!(!matcher.withholds());
+    }
+
+    #[test]
+    fn combos() {
+        let combos = ["j k", "<LCtrl> <RCtrl> q", "<Ctrl> <Ctrl> w"].map(|keys| Combo {
+            keys: keys.parse::<PatternSequence>().unwrap().0,
+            action: ChordAction::Quit,
+            profile: None,
+        });
+        let mut matcher = ComboMatcher::new(Duration::from_millis(50));
+        matcher.set_combos(&combos, DEFAULT_PROFILE);
+        assert_eq!(matcher.press(Regular(K)), ComboPress::Held);
+        assert_eq!(matcher.press(Regular(J)), ComboPress::Fired(0));
+        assert_eq!(matcher.deadline(), None);
+
+        assert_eq!(matcher.press(Regular(J)), ComboPress::Held);
+        assert!(matcher.deadline().is_some());
+        assert_eq!(
+            matcher.press(Regular(L)),
+            ComboPress::Pass(vec![Regular(J)])
+        );
+        assert_eq!(matcher.press(Regular(L)), ComboPress::Pass(Vec::new()));
+
+        assert_eq!(matcher.press(Modifier(RightCtrl)), ComboPress::Held);
+        assert_eq!(matcher.press(Modifier(LeftCtrl)), ComboPress::Held);
+        assert_eq!(matcher.release(Regular(Q)), Vec::new());
+        assert_eq!(matcher.press(Regular(W)), ComboPress::Fired(2));
+
+        assert_eq!(matcher.press(Modifier(LeftCtrl)), ComboPress::Held);
+        assert_eq!(
+            matcher.release(Modifier(LeftCtrl)),
+            vec![Modifier(LeftCtrl)]
+        );
+        assert_eq!(matcher.press(Regular(J)), ComboPress::Held);
+        assert_eq!(matcher.expire(), vec![Regular(J)]);
+    }
+
+    #[test]
+    fn hold_to_quit() {
+        let start = SystemTime::UNIX_EPOCH;
+        let after = |millis| start + Duration::from_millis(millis);
+        let mut hold_to_quit = HoldToQuit::new(
+            vec![KeyPattern::Key(Regular(Escape))],
+            Duration::from_secs(1),
+        );
+        hold_to_quit.update(true, start);
+        hold_to_quit.update(true, after(500));
+        assert!(!hold_to_quit.is_due(after(999)));
+        assert!(hold_to_quit.is_due(after(1000)));
+        hold_to_quit.update(false, after(1100));
+        hold_to_quit.update(true, after(1200));
+        assert!(!hold_to_quit.is_due(after(2100)));
+
+        let mut disabled = HoldToQuit::new(Vec::new(), Duration::from_secs(1));
+        disabled.update(true, start);
+        assert_eq!(disabled.deadline(), None);
+        assert!(!disabled.withhold(Regular(Escape), true));
+
+        // Only the key completing the hold is withheld
+        let keys = "<Ctrl> <Esc>".parse::<PatternSequence>().unwrap().0;
+        let mut hold_to_quit = HoldToQuit::new(keys, Duration::from_secs(1));
+        assert!(!hold_to_quit.withhold(Modifier(RightCtrl), false));
+        assert!(!hold_to_quit.withhold(Regular(Q), true));
+        assert!(hold_to_quit.withhold(Regular(Escape), true));
+        assert_eq!(hold_to_quit.let_through(), Some(Regular(Escape)));
+        assert_eq!(hold_to_quit.withheld(), None);
+    }
+
+    #[test]
+    fn either_side_modifiers() {
+        let mut matcher = matcher(&["~ ."]);
+        matcher.feed(Regular(Enter));
+        matcher.feed(Modifier(RightShift));
+        matcher.feed(Regular(Grave));
+        assert_eq!(matcher.feed(Regular(Period)), completed(0));
+    }
+
+    /// Keys continue chords through every pattern matching them
+    #[test]
+    fn pattern_chords() {
+        let mut matcher = matcher(&["<Any-Digit> a", "1 b", "<!Esc>"]);
+        matcher.feed(Regular(Enter));
+        matcher.feed(Regular(Num2));
+        assert_eq!(matcher.feed(Regular(A)), completed(0));
+
+        matcher.feed(Regular(Enter));
+        matcher.feed(Regular(Num1));
+        assert_eq!(matcher.feed(Regular(B)), completed(1));
+
+        matcher.feed(Regular(Enter));
+        assert_eq!(matcher.feed(Regular(Q)), completed(2));
+        matcher.feed(Regular(Enter));
+        assert_eq!(matcher.feed(Regular(Escape)), None);
+    }
+
+    /// A chord starting with an exact key doesn't hide one with a pattern
+    #[test]
+    fn overlapping_patterns() {
+        let mut matcher = matcher(&["~ .", "<LShift> x", "a", "<Any-Letter>"]);
+        matcher.feed(Regular(Enter));
+        matcher.feed(Modifier(LeftShift));
+        matcher.feed(Regular(Grave));
+        assert_eq!(matcher.feed(Regular(Period)), completed(0));
+        matcher.feed(Regular(Enter));
+        matcher.feed(Modifier(LeftShift));
+        assert_eq!(matcher.feed(Regular(X)), completed(1));
+        matcher.feed(Regular(Enter));
+        assert_eq!(matcher.feed(Regular(A)), completed(2));
+        matcher.feed(Regular(Enter));
+        assert_eq!(matcher.feed(Regular(B)), completed(3));
+    }
+}
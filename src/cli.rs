@@ -0,0 +1,221 @@
+/*!
+ * Keyboard Bridge for Raspberry Pi - Command-line interface
+ * Created by sheepy0125 on 2026-10-18 under the MIT license
+**/
+
+/***** Setup *****/
+use crate::{
+    config::*,
+    discovery::*,
+    key::KeyCode,
+    pattern::{KeyPattern, PatternSequence},
+    Keyboard, ProcessedEvent, UsbReport,
+};
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use evdev::EventType;
+use std::path::PathBuf;
+
+/***** Structs *****/
+/// A bridge from an evdev keyboard to a USB HID gadget
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Configuration file to use instead of the user or system one
+    #[arg(short, long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Bridge the keyboard to the USB gadget (the default)
+    Run(RunArgs),
+    /// List the evdev input devices and which would be grabbed
+    ListDevices,
+    /// Print decoded keys and USB reports without grabbing the keyboard or writing to the gadget
+    Monitor(MonitorArgs),
+    /// Validate the configuration file and print the effective configuration
+    CheckConfig,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct RunArgs {
+    /// Keyboard device to grab, overriding the configuration. Can be given several times.
+    #[arg(short, long, value_name = "PATH")]
+    pub device: Vec<PathBuf>,
+    /// USB gadget device to write to, overriding the configuration
+    #[arg(short, long, value_name = "PATH")]
+    pub gadget: Option<PathBuf>,
+}
+impl RunArgs {
+    /// Apply the overrides to a loaded configuration
+    pub fn apply(&self, config: &mut Config) {
+        if !self.device.is_empty() {
+            let keyboards = self
+                .device
+                .iter()
+                .map(|device| KeyboardConfig {
+                    device: Some(device.clone()),
+                    ..Default::default()
+                })
+                .collect();
+            *config.keyboards.get_mut() = keyboards;
+        }
+        if let Some(gadget) = &self.gadget {
+            config.gadget.path = gadget.clone();
+        }
+    }
+}
+
+#[derive(Debug, Default, Args)]
+pub struct MonitorArgs {
+    /// Keyboard device to read, overriding the configuration. Can be given several times.
+    #[arg(short, long, value_name = "PATH")]
+    pub device: Vec<PathBuf>,
+}
+
+/***** Subcommands *****/
+
+/// `list-devices`
+pub fn list_devices(config: &Config) -> Result<()> {
+    let mut devices = evdev::enumerate().collect::<Vec<_>>();
+    devices.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if devices.is_empty() {
+        println!("No input devices found (are you in the `input` group?)");
+    }
+    for (path, device) in devices {
+        let input_id = device.input_id();
+        println!("{}", path.display());
+        println!("    Name:     {}", device.name().unwrap_or("<unnamed>"));
+        println!(
+            "    ID:       {:04x}:{:04x}",
+            input_id.vendor(),
+            input_id.product()
+        );
+        println!(
+            "    Phys:     {}",
+            device.physical_path().unwrap_or("<none>")
+        );
+        println!(
+            "    Has keys: {}",
+            device.supported_events().contains(EventType::KEY)
+        );
+        println!("    Keyboard: {}", is_keyboard(&device));
+        for link in by_id_links(&path) {
+            println!("    By ID:    {}", link.display());
+        }
+        println!(
+            "    Selected: {}",
+            matches_any(config.keyboards.get_ref(), &path, &device)
+        );
+    }
+    Ok(())
+}
+
+/// `monitor`
+pub async fn monitor(config: &Config, args: &MonitorArgs) -> Result<()> {
+    let device_paths = match args.device.as_slice() {
+        [] => find_keyboards_or_fail(config.keyboards.get_ref())?,
+        devices => devices.to_vec(),
+    };
+    let mut keyboard = Keyboard::new(&config.chords, &config.gadget);
+    keyboard.chords_enabled = false;
+    for device_path in &device_paths {
+        keyboard
+            .attach(device_path, false)
+            .with_context(|| format!("Open keyboard at {}", device_path.display()))?;
+        println!("Monitoring {}.", device_path.display());
+    }
+    println!("Keys are still sent to other programs. Exit with Ctrl+C.");
+    loop {
+        let (device_idx, event) = keyboard.next_event().await;
+        let event = event.with_context(|| {
+            format!(
+                "Fetch next event of {}",
+                keyboard.devices[device_idx].path.display()
+            )
+        })?;
+        match keyboard.process_event(device_idx, event) {
+            ProcessedEvent::Key => println!(
+                "{}: {:?} (value {})",
+                keyboard.devices[device_idx].name,
+                KeyCode::from(event),
+                event.value()
+            ),
+            ProcessedEvent::EndOfFrame => {
+                for report in keyboard.changed_reports() {
+                    match report {
+                        UsbReport::Keyboard(report) => println!("    -> {report:02x?}"),
+                        UsbReport::Control(report) => println!("    -> control {report:02x?}"),
+                    }
+                }
+            }
+            ProcessedEvent::Ignored => {}
+        }
+    }
+}
+
+/// `check-config`
+pub fn check_config(config: &Config) -> Result<()> {
+    match &config.path {
+        Some(path) => println!("Configuration at {} is valid.", path.display()),
+        None => println!("No configuration file found, the defaults are used."),
+    }
+    let keyboards = find_keyboards(config.keyboards.get_ref());
+    if keyboards.is_empty() {
+        println!("Keyboards:       no plugged in device matches");
+    }
+    for keyboard in keyboards {
+        println!("Keyboard:        {}", keyboard.display());
+    }
+    println!("USB gadget:      {}", config.gadget.path.display());
+    println!("Control gadget:  {}", config.gadget.control_path.display());
+    println!("Write timeout:   {:?}", config.gadget.write_timeout());
+    println!("Reopen interval: {:?}", config.gadget.reopen_interval());
+    println!(
+        "Disconnected:    {:?} (hold limit {})",
+        config.gadget.while_disconnected, config.gadget.hold_limit
+    );
+    println!("Report mode:     {:?}", config.gadget.report_mode);
+    println!("Block system:    {}", config.gadget.block_system_keys);
+    println!("Quit chord:      {}", config.chords.quit_chord());
+    match config.chords.hold_to_quit.get_ref().0.as_slice() {
+        [] => println!("Hold to quit:    disabled"),
+        keys => println!(
+            "Hold to quit:    {} for {:?}",
+            keys.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" + "),
+            config.chords.hold_to_quit_duration()
+        ),
+    }
+    for chord in config.chords.chords().into_iter().skip(1) {
+        let keys =
+            std::iter::once(KeyPattern::Key(config.chords.start_key())).chain(chord.sequence);
+        let profile = match &chord.profile {
+            Some(profile) => format!(" (in the {profile} profile)"),
+            None => String::new(),
+        };
+        println!(
+            "Chord:           {} -> {:?}{profile}",
+            keys.collect::<PatternSequence>(),
+            chord.action
+        );
+    }
+    for combo in config.chords.combos() {
+        let keys = combo.keys.iter().map(ToString::to_string);
+        let profile = match &combo.profile {
+            Some(profile) => format!(" (in the {profile} profile)"),
+            None => String::new(),
+        };
+        println!(
+            "Combo:           {} -> {:?}{profile}",
+            keys.collect::<Vec<_>>().join(" + "),
+            combo.action
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,742 @@
+/*!
+ * Keyboard Bridge for Raspberry Pi - Configuration
+ * Created by sheepy0125 on 2026-10-18 under the MIT license
+**/
+
+/***** Setup *****/
+use crate::{chord::*, key::*, pattern::*};
+use anyhow::{anyhow, Context, Error, Result};
+use log::{debug, info};
+use serde::{
+    de::{
+        self,
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        MapAccess, SeqAccess, Visitor,
+    },
+    Deserialize, Deserializer,
+};
+use std::{
+    env, fmt, fs,
+    marker::PhantomData,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use toml::Spanned;
+// Constants
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/keyboard-bridge.toml";
+/// Relative to `$XDG_CONFIG_HOME` (or `~/.config`)
+const USER_CONFIG_PATH: &str = "keyboard-bridge/config.toml";
+const DEFAULT_USB_GADGET_DEVICE_PATH: &str = "/dev/hidg0";
+const DEFAULT_USB_CONTROL_GADGET_DEVICE_PATH: &str = "/dev/hidg1";
+const DEFAULT_WRITE_TIMEOUT_MS: u64 = 250_u64;
+const DEFAULT_REOPEN_INTERVAL_MS: u64 = 1000_u64;
+const DEFAULT_HOLD_LIMIT: usize = 64_usize;
+const DEFAULT_CHORD_TIMEOUT_MS: u64 = 1000_u64;
+const DEFAULT_COMBO_WINDOW_MS: u64 = 50_u64;
+const DEFAULT_HOLD_TO_QUIT_MS: u64 = 5000_u64;
+
+/***** Structs *****/
+/// The whole configuration file
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Either one `[keyboard]` table or several `[[keyboard]]` tables. A
+    /// device matching any of them is grabbed.
+    #[serde(rename = "keyboard", deserialize_with = "one_or_many")]
+    pub keyboards: Spanned<Vec<KeyboardConfig>>,
+    pub gadget: GadgetConfig,
+    pub chords: ChordConfig,
+    /// Where the configuration was loaded from, if not the defaults
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keyboards: Spanned::new(0..0, vec![KeyboardConfig::default()]),
+            gadget: GadgetConfig::default(),
+            chords: ChordConfig::default(),
+            path: None,
+        }
+    }
+}
+
+/// `[keyboard]`: which evdev devices to grab. Without a device path nor any
+/// match options, every device that looks like a keyboard is grabbed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    /// Grab this device path, skipping discovery altogether
+    pub device: Option<PathBuf>,
+    /// Match devices whose name contains this
+    pub name: Option<String>,
+    /// Match devices with this USB vendor ID
+    pub vendor: Option<u16>,
+    /// Match devices with this USB product ID
+    pub product: Option<u16>,
+    /// Match devices whose physical path contains this
+    pub phys: Option<String>,
+    /// Match the device a `/dev/input/by-id` symlink (name or full path) points to
+    pub by_id: Option<String>,
+}
+impl KeyboardConfig {
+    pub fn has_match_options(&self) -> bool {
+        self.name.is_some()
+            || self.vendor.is_some()
+            || self.product.is_some()
+            || self.phys.is_some()
+            || self.by_id.is_some()
+    }
+}
+
+/// `[gadget]`: the USB HID gadget to write reports to
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GadgetConfig {
+    pub path: PathBuf,
+    /// The HID gadget for consumer control reports (media keys) and system
+    /// control reports (power, sleep and wake up keys)
+    pub control_path: PathBuf,
+    /// Whether to never send the power, sleep and wake up keys to the host
+    pub block_system_keys: bool,
+    /// How long to wait for the host to read the previous report before
+    /// giving up on a report, until the host reads again
+    pub write_timeout_ms: Spanned<u64>,
+    /// How often to try reopening the gadget after the host went away
+    pub reopen_interval_ms: Spanned<u64>,
+    /// The USB device controller to watch the host's state on, in
+    /// `/sys/class/udc`. Defaults to the first one.
+    pub udc: Option<String>,
+    /// What to do with reports while the host is disconnected or asleep
+    pub while_disconnected: DisconnectedPolicy,
+    /// How many reports are held at most, dropping the oldest
+    pub hold_limit: usize,
+    /// The report format, which must match the gadget's report descriptor
+    pub report_mode: ReportMode,
+}
+impl Default for GadgetConfig {
+    fn default() -> Self {
+        Self {
+            path: DEFAULT_USB_GADGET_DEVICE_PATH.into(),
+            control_path: DEFAULT_USB_CONTROL_GADGET_DEVICE_PATH.into(),
+            block_system_keys: false,
+            write_timeout_ms: Spanned::new(0..0, DEFAULT_WRITE_TIMEOUT_MS),
+            reopen_interval_ms: Spanned::new(0..0, DEFAULT_REOPEN_INTERVAL_MS),
+            udc: None,
+            while_disconnected: DisconnectedPolicy::default(),
+            hold_limit: DEFAULT_HOLD_LIMIT,
+            report_mode: ReportMode::default(),
+        }
+    }
+}
+impl GadgetConfig {
+    pub fn write_timeout(&self) -> Duration {
+        Duration::from_millis(*self.write_timeout_ms.get_ref())
+    }
+
+    pub fn reopen_interval(&self) -> Duration {
+        Duration::from_millis(*self.reopen_interval_ms.get_ref())
+    }
+}
+
+/// `gadget.while_disconnected`
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisconnectedPolicy {
+    /// Throw reports away; the host only sees keys pressed after it's back
+    #[default]
+    Drop,
+    /// Send the reports once the host is back, up to `gadget.hold_limit`
+    Hold,
+}
+
+/// `gadget.report_mode`
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportMode {
+    /// The 8 byte boot protocol report: the modifiers and up to 6 keys
+    #[default]
+    Boot,
+    /// N-key rollover: the modifiers and a bitmap of every key
+    Nkro,
+}
+
+/// `[chords]`: the chord start key and chord sequences
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChordConfig {
+    pub start_key: Spanned<ConfigKey>,
+    /// The chord sequence (without the start key) that exits the bridge
+    pub quit: Spanned<ConfigPatterns>,
+    /// Whether the quit chord's keys are kept from the host (see
+    /// `ChordDefinition::consume`)
+    pub consume_quit: bool,
+    /// How long to wait for the next key of a chord before giving up on it,
+    /// or firing it if it's the start of a longer one
+    pub timeout_ms: Spanned<u64>,
+    /// Keys to hold together to quit, without typing anything on the host.
+    /// Empty (the default) disables holding to quit.
+    pub hold_to_quit: Spanned<ConfigPatterns>,
+    /// How long to hold them
+    pub hold_to_quit_ms: Spanned<u64>,
+    /// How long after a combo's first key its other keys must be pressed
+    pub combo_window_ms: Spanned<u64>,
+    /// `[[chords.combo]]`: keys pressed together
+    #[serde(rename = "combo")]
+    pub combos: Vec<Spanned<ComboDefinition>>,
+    /// `[[chords.chord]]`: more chords
+    #[serde(rename = "chord")]
+    pub extra: Vec<Spanned<ChordDefinition>>,
+}
+impl Default for ChordConfig {
+    fn default() -> Self {
+        Self {
+            start_key: Spanned::new(0..0, ConfigKey(CHORD_SEQUENCE_START_KEY)),
+            quit: Spanned::new(0..0, ConfigPatterns(QUIT_CHORD_SEQUENCE.to_vec())),
+            consume_quit: false,
+            timeout_ms: Spanned::new(0..0, DEFAULT_CHORD_TIMEOUT_MS),
+            hold_to_quit: Spanned::new(0..0, ConfigPatterns(Vec::new())),
+            hold_to_quit_ms: Spanned::new(0..0, DEFAULT_HOLD_TO_QUIT_MS),
+            combo_window_ms: Spanned::new(0..0, DEFAULT_COMBO_WINDOW_MS),
+            combos: Vec::new(),
+            extra: Vec::new(),
+        }
+    }
+}
+impl ChordConfig {
+    pub fn start_key(&self) -> KeyCode {
+        self.start_key.get_ref().0
+    }
+
+    pub fn quit_sequence(&self) -> &ChordSequence {
+        &self.quit.get_ref().0
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(*self.timeout_ms.get_ref())
+    }
+
+    pub fn hold_to_quit_duration(&self) -> Duration {
+        Duration::from_millis(*self.hold_to_quit_ms.get_ref())
+    }
+
+    pub fn combo_window(&self) -> Duration {
+        Duration::from_millis(*self.combo_window_ms.get_ref())
+    }
+
+    pub fn combos(&self) -> Vec<Combo> {
+        (self.combos.iter())
+            .map(|combo| Combo {
+                keys: combo.get_ref().keys.0.clone(),
+                action: combo.get_ref().action.clone(),
+                profile: combo.get_ref().profile.clone(),
+            })
+            .collect()
+    }
+
+    /// Every key to type to quit, starting with the start key
+    pub fn quit_chord(&self) -> PatternSequence {
+        std::iter::once(KeyPattern::Key(self.start_key()))
+            .chain(self.quit_sequence().iter().cloned())
+            .collect()
+    }
+
+    /// Every chord to listen for, the quit chord first
+    pub fn chords(&self) -> Vec<Chord> {
+        let quit = Chord {
+            sequence: self.quit_sequence().to_vec(),
+            action: ChordAction::Quit,
+            profile: None,
+            consume: self.consume_quit,
+        };
+        std::iter::once(quit)
+            .chain(self.extra.iter().map(|chord| Chord {
+                sequence: chord.get_ref().keys.0.clone(),
+                action: chord.get_ref().action.clone(),
+                profile: chord.get_ref().profile.clone(),
+                consume: chord.get_ref().consume,
+            }))
+            .collect()
+    }
+
+    /// The reason a chord sequence can never be typed, if any
+    fn unreachable_reason(&self, sequence: &ChordSequence) -> Option<String> {
+        if sequence.contains(&KeyPattern::Key(self.start_key())) {
+            return Some(format!(
+                "it contains the start key ({}), which starts over",
+                self.start_key()
+            ));
+        }
+        if sequence.contains(&KeyPattern::Key(KeyCode::Unknown)) {
+            return Some("it contains an unknown key".to_string());
+        }
+        no_key_reason(sequence)
+    }
+}
+
+/// `[[chords.chord]]`: a chord sequence (without the start key) and its action
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChordDefinition {
+    pub keys: ConfigPatterns,
+    pub action: ChordAction,
+    /// Only listen for the chord in this profile
+    pub profile: Option<String>,
+    /// Keep the chord's keys from the host while it may be typed, sending
+    /// them only if it isn't
+    #[serde(default)]
+    pub consume: bool,
+}
+
+/// `[[chords.combo]]`: keys pressed together and their action
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ComboDefinition {
+    pub keys: ConfigPatterns,
+    pub action: ChordAction,
+    /// Only look for the combo in this profile
+    pub profile: Option<String>,
+}
+
+/// A key code written by name (see `FromStr for KeyCode`) in the
+/// configuration file
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ConfigKey(pub KeyCode);
+impl<'de> Deserialize<'de> for ConfigKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map(Self).map_err(de::Error::custom)
+    }
+}
+
+/// Key codes written in sequence notation (see `KeySequence`) or as a list of
+/// names in the configuration file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConfigKeys(pub Vec<KeyCode>);
+impl<'de> Deserialize<'de> for ConfigKeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let keys = deserialize_sequence::<_, KeySequence, KeyCode>(
+            deserializer,
+            "a key sequence or an array of key names",
+        )?;
+        Ok(Self(keys.0))
+    }
+}
+
+/// Key patterns written in sequence notation (see `PatternSequence`) or as a
+/// list of names in the configuration file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConfigPatterns(pub Vec<KeyPattern>);
+impl<'de> Deserialize<'de> for ConfigPatterns {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let patterns = deserialize_sequence::<_, PatternSequence, KeyPattern>(
+            deserializer,
+            "a pattern sequence or an array of pattern names",
+        )?;
+        Ok(Self(patterns.0))
+    }
+}
+
+/// Deserialize either a single table or an array of tables into a list
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Spanned<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct OneOrMany<T>(Vec<T>);
+    impl<'de, T: Deserialize<'de>> Deserialize<'de> for OneOrMany<T> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(OneOrManyVisitor(PhantomData))
+        }
+    }
+    struct OneOrManyVisitor<T>(PhantomData<T>);
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrManyVisitor<T> {
+        type Value = OneOrMany<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a table or an array of tables")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            T::deserialize(MapAccessDeserializer::new(map)).map(|one| OneOrMany(vec![one]))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Vec::deserialize(SeqAccessDeserializer::new(seq)).map(OneOrMany)
+        }
+    }
+
+    let spanned = Spanned::<OneOrMany<T>>::deserialize(deserializer)?;
+    let span = spanned.span();
+    Ok(Spanned::new(span, spanned.into_inner().0))
+}
+
+/***** Loading *****/
+impl Config {
+    /// Load the configuration from `path`, or from the first of the user and
+    /// system configuration files that exists. Falls back to the defaults.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_paths().into_iter().find(|path| path.exists()) {
+                Some(path) => path,
+                None => {
+                    info!("No configuration file found, using the defaults.");
+                    return Ok(Self::default());
+                }
+            },
+        };
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("Read config file at {}", path.display()))?;
+        let config = Self::parse(&source, &path)?;
+        info!("Loaded configuration from {}.", path.display());
+        Ok(config)
+    }
+
+    /// The configuration files looked for, in order of precedence
+    pub fn default_paths() -> Vec<PathBuf> {
+        let user_config_dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        let mut paths = Vec::new();
+        if let Some(user_config_dir) = user_config_dir {
+            paths.push(user_config_dir.join(USER_CONFIG_PATH));
+        }
+        paths.push(SYSTEM_CONFIG_PATH.into());
+        paths
+    }
+
+    /// Parse and validate a configuration file's contents
+    pub fn parse(source: &str, path: &Path) -> Result<Self> {
+        let mut config: Self = toml::from_str(source)
+            .map_err(Error::msg)
+            .with_context(|| format!("Parse config file at {}", path.display()))?;
+        config.path = Some(path.to_path_buf());
+        config.validate(source)?;
+        debug!("Configuration: {config:?}");
+        Ok(config)
+    }
+
+    /// Check the values that parsed fine but do not make sense
+    fn validate(&self, source: &str) -> Result<()> {
+        let error_at = |span: Range<usize>, message: &str| -> Error {
+            let path = self.path.as_deref().unwrap_or(Path::new("<config>"));
+            anyhow!("{}:{}: {message}", path.display(), line_of(source, span))
+        };
+
+        if self.keyboards.get_ref().is_empty() {
+            return Err(error_at(
+                self.keyboards.span(),
+                "`keyboard` must contain at least one table",
+            ));
+        }
+        if *self.gadget.write_timeout_ms.get_ref() == 0 {
+            return Err(error_at(
+                self.gadget.write_timeout_ms.span(),
+                "`gadget.write_timeout_ms` must be at least 1",
+            ));
+        }
+        // The gadget is reopened in a loop sleeping this long
+        if *self.gadget.reopen_interval_ms.get_ref() == 0 {
+            return Err(error_at(
+                self.gadget.reopen_interval_ms.span(),
+                "`gadget.reopen_interval_ms` must be at least 1",
+            ));
+        }
+        if *self.chords.hold_to_quit_ms.get_ref() == 0 {
+            return Err(error_at(
+                self.chords.hold_to_quit_ms.span(),
+                "`chords.hold_to_quit_ms` must be at least 1",
+            ));
+        }
+        let hold_to_quit = &self.chords.hold_to_quit.get_ref().0;
+        if hold_to_quit.contains(&KeyPattern::Key(KeyCode::Unknown)) {
+            return Err(error_at(
+                self.chords.hold_to_quit.span(),
+                "`chords.hold_to_quit` can't contain an unknown key",
+            ));
+        }
+        // The key completing the hold is kept from the host, so matching
+        // more than one key (or side) would keep most keys from it
+        if let Some(pattern) = (hold_to_quit.iter())
+            .find(|pattern| !matches!(pattern, KeyPattern::Key(_) | KeyPattern::EitherSide(_)))
+        {
+            return Err(error_at(
+                self.chords.hold_to_quit.span(),
+                &format!(
+                    "`chords.hold_to_quit` can only contain keys and either-side modifiers, \
+                     not `<{pattern}>`"
+                ),
+            ));
+        }
+        if *self.chords.combo_window_ms.get_ref() == 0 {
+            return Err(error_at(
+                self.chords.combo_window_ms.span(),
+                "`chords.combo_window_ms` must be at least 1",
+            ));
+        }
+        if *self.chords.timeout_ms.get_ref() == 0 {
+            return Err(error_at(
+                self.chords.timeout_ms.span(),
+                "`chords.timeout_ms` must be at least 1",
+            ));
+        }
+        // The quit chord is checked along with the others, so it's always
+        // reachable. The default one can only be broken by the start key.
+        let quit_span = match self.chords.quit.span() {
+            span if span.is_empty() => self.chords.start_key.span(),
+            span => span,
+        };
+        let spans = std::iter::once(quit_span)
+            .chain(self.chords.extra.iter().map(|chord| chord.span()))
+            .collect::<Vec<_>>();
+        let chords = self.chords.chords();
+        let profiles = (chords.iter().map(|chord| &chord.profile))
+            .chain(
+                self.chords
+                    .combos
+                    .iter()
+                    .map(|combo| &combo.get_ref().profile),
+            )
+            .filter_map(|profile| profile.as_deref())
+            .chain([DEFAULT_PROFILE])
+            .collect::<Vec<_>>();
+        // Which chords fire is up to the matcher of the profile they're typed in
+        let matchers = (profiles.iter())
+            .map(|profile| {
+                let mut matcher = ChordMatcher::new(self.chords.start_key(), self.chords.timeout());
+                matcher.set_chords(&chords, profile);
+                (*profile, matcher)
+            })
+            .collect::<Vec<_>>();
+        for (idx, (span, chord)) in spans.iter().zip(&chords).enumerate() {
+            let name = match idx {
+                0 => "`chords.quit`".to_string(),
+                idx => format!("Chord {idx}"),
+            };
+            let error = |message: String| error_at(span.clone(), &format!("{name} {message}"));
+            let sequence = chord.sequence.as_slice();
+            if sequence.is_empty() {
+                return Err(error("must contain at least one key".to_string()));
+            }
+            if let Some(reason) = self.chords.unreachable_reason(sequence) {
+                return Err(error(format!("can never be typed: {reason}")));
+            }
+            for (other_idx, other) in chords.iter().enumerate() {
+                // Chords in different profiles are never listened for together
+                if other_idx == idx || !same_profile(&chord.profile, &other.profile) {
+                    continue;
+                }
+                if other_idx < idx && other.sequence == sequence {
+                    return Err(error("is a duplicate of an earlier chord".to_string()));
+                }
+            }
+            // Hidden in every profile it's listened for in
+            let hiding_chords = (matchers.iter())
+                .filter(|(profile, _)| in_profile(&chord.profile, profile))
+                .map(|(_, matcher)| matcher.hiding_chord(&chords, idx))
+                .collect::<Option<Vec<_>>>();
+            if let Some(&hiding_idx) = hiding_chords.as_deref().and_then(<[_]>::first) {
+                let hiding_name = match hiding_idx {
+                    0 => "the quit chord".to_string(),
+                    idx => format!("chord {idx}"),
+                };
+                return Err(error(format!(
+                    "can never be typed: {hiding_name} matches the same keys and fires instead"
+                )));
+            }
+            if let Some(reason) = action_error(&chord.action, &profiles) {
+                return Err(error(reason));
+            }
+        }
+        let combos = self.chords.combos();
+        for (idx, (span, combo)) in (self.chords.combos.iter())
+            .map(|combo| combo.span())
+            .zip(&combos)
+            .enumerate()
+        {
+            let error =
+                |message: String| error_at(span.clone(), &format!("Combo {} {message}", idx + 1));
+            if combo.keys.len() < 2 {
+                return Err(error("must contain at least two keys".to_string()));
+            }
+            if combo.keys.contains(&KeyPattern::Key(KeyCode::Unknown)) {
+                return Err(error(
+                    "can never be pressed: it contains an unknown key".to_string(),
+                ));
+            }
+            if let Some(reason) = no_key_reason(&combo.keys) {
+                return Err(error(format!("can never be pressed: {reason}")));
+            }
+            // Other patterns may be pressed twice, with different keys
+            if let Some(key_code) = (combo.keys.iter())
+                .enumerate()
+                .filter_map(|(key_idx, pattern)| Some((key_idx, pattern.key()?)))
+                .find_map(|(key_idx, key_code)| {
+                    (combo.keys[..key_idx].contains(&KeyPattern::Key(key_code))).then_some(key_code)
+                })
+            {
+                return Err(error(format!(
+                    "can never be pressed: it contains {key_code} twice"
+                )));
+            }
+            for other in &combos[..idx] {
+                let count = |keys: &[KeyPattern], pattern| {
+                    keys.iter().filter(|other| *other == pattern).count()
+                };
+                if same_profile(&combo.profile, &other.profile)
+                    && other.keys.len() == combo.keys.len()
+                    && (combo.keys.iter())
+                        .all(|pattern| count(&other.keys, pattern) == count(&combo.keys, pattern))
+                {
+                    return Err(error("is a duplicate of an earlier combo".to_string()));
+                }
+            }
+            if let Some(reason) = action_error(&combo.action, &profiles) {
+                return Err(error(reason));
+            }
+        }
+        Ok(())
+    }
+}
+
+/***** Auxiliary functions *****/
+
+/// Why some key can't be typed, if a pattern matches no key
+fn no_key_reason(patterns: &[KeyPattern]) -> Option<String> {
+    (patterns.iter())
+        .find(|pattern| !KeyCode::all().any(|key_code| pattern.matches(key_code)))
+        .map(|pattern| format!("`<{pattern}>` matches no key"))
+}
+
+/// Why a chord's or combo's action can't be done, if it can't
+fn action_error(action: &ChordAction, profiles: &[&str]) -> Option<String> {
+    match action {
+        ChordAction::Type(text) => (text.chars())
+            .find(|character| RegularKey::from_typed_char(*character).is_none())
+            .map(|character| format!("can't type {character:?}")),
+        ChordAction::Run { timeout_ms: 0, .. } => {
+            Some("must have a `timeout_ms` of at least 1".to_string())
+        }
+        ChordAction::SwitchProfile(profile) if !profiles.contains(&profile.as_str()) => Some(
+            format!("switches to the {profile} profile, which has no chords nor combos"),
+        ),
+        ChordAction::Tap(keys) if keys.0.is_empty() => {
+            Some("must tap at least one key".to_string())
+        }
+        ChordAction::Tap(keys) => (keys.0.iter())
+            .find(|key_code| !matches!(key_code, KeyCode::Regular(_) | KeyCode::Modifier(_)))
+            .map(|key_code| format!("can't tap {key_code}, only keyboard keys")),
+        _ => None,
+    }
+}
+
+/// The 1-indexed line a byte span starts on
+fn line_of(source: &str, span: Range<usize>) -> usize {
+    let start = span.start.min(source.len());
+    source[..start].matches('\n').count() + 1
+}
+
+/***** Tests *****/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Config> {
+        Config::parse(source, Path::new("test.toml"))
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = parse("[gadget]\nhold_limit = 8\nwrite_timeout_ms = 0\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.toml:3: `gadget.write_timeout_ms` must be at least 1"
+        );
+        let error = parse("[gadget]\nreopen_interval_ms = 0\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.toml:2: `gadget.reopen_interval_ms` must be at least 1"
+        );
+        // Values that don't parse are reported by line too
+        let error = parse("[chords]\nstart_key = \"NotAKey\"\n").unwrap_err();
+        assert!(format!("{error:#}").contains("line 2"), "{error:#}");
+        assert!(parse("").is_ok());
+    }
+
+    #[test]
+    fn hold_to_quit_patterns() {
+        parse("[chords]\nhold_to_quit = \"<Ctrl> <Alt> <Esc>\"\n").unwrap();
+        for pattern in ["<Any>", "<Any-Letter>", "<!Esc>"] {
+            let error =
+                parse(&format!("[chords]\nhold_to_quit = \"<Esc> {pattern}\"\n")).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "test.toml:2: `chords.hold_to_quit` can only contain keys and either-side \
+                     modifiers, not `{pattern}`"
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_chords() {
+        let error = parse(
+            "[[chords.chord]]\nkeys = \"~ h\"\naction = \"quit\"\n\n\
+             [[chords.chord]]\nkeys = [\"Shift\", \"Grave\", \"H\"]\naction = \"release-all\"\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.toml:5: Chord 2 is a duplicate of an earlier chord"
+        );
+
+        let error =
+            parse("[[chords.chord]]\nkeys = \"a <Enter> b\"\naction = \"quit\"\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.toml:1: Chord 1 can never be typed: it contains the start key (Enter), \
+             which starts over"
+        );
+
+        // The quit chord is checked like the others
+        let error = parse("[chords]\nstart_key = \"Esc\"\nquit = \"<Esc> q\"\n").unwrap_err();
+        assert!(error.to_string().starts_with("test.toml:3: `chords.quit`"));
+        // As is the default one, by the start key breaking it
+        let error = parse("[chords]\nstart_key = \"BS\"\n").unwrap_err();
+        assert!(error.to_string().starts_with("test.toml:2: `chords.quit`"));
+
+        // A pattern chord completed by every key of a later one hides it
+        let error = parse(
+            "[[chords.chord]]\nkeys = \"<Any>\"\naction = \"quit\"\n\
+             [[chords.chord]]\nkeys = \"<Any-Letter>\"\naction = \"release-all\"\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "test.toml:4: Chord 2 can never be typed: chord 1 matches the same keys and fires \
+             instead"
+        );
+        // Exact keys win over patterns, and a narrower pattern listed first wins
+        parse(
+            "[[chords.chord]]\nkeys = \"<Any>\"\naction = \"quit\"\n\
+             [[chords.chord]]\nkeys = \"a\"\naction = \"release-all\"\n",
+        )
+        .unwrap();
+        parse(
+            "[[chords.chord]]\nkeys = \"<Any-Letter>\"\naction = \"quit\"\n\
+             [[chords.chord]]\nkeys = \"<Any>\"\naction = \"release-all\"\n",
+        )
+        .unwrap();
+
+        // The same keys in different profiles are fine
+        parse(
+            "[[chords.chord]]\nkeys = \"~ g\"\nprofile = \"a\"\naction = { switch-profile = \"b\" }\n\
+             [[chords.chord]]\nkeys = \"~ g\"\nprofile = \"b\"\naction = { switch-profile = \"a\" }\n",
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+/*!
+ * Keyboard Bridge for Raspberry Pi - Keyboard discovery
+ * Created by sheepy0125 on 2026-10-18 under the MIT license
+**/
+
+/***** Setup *****/
+use crate::config::KeyboardConfig;
+use anyhow::{bail, Result};
+use evdev::{Device, EventType, Key};
+use log::{debug, trace};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+// Constants
+const BY_ID_PATH: &str = "/dev/input/by-id";
+/// A device needs all of these to be considered a keyboard
+#[rustfmt::skip]
+const KEYBOARD_KEYS: &[Key] = &[
+    Key::KEY_A, Key::KEY_B, Key::KEY_C, Key::KEY_D, Key::KEY_E, Key::KEY_F, Key::KEY_G,
+    Key::KEY_H, Key::KEY_I, Key::KEY_J, Key::KEY_K, Key::KEY_L, Key::KEY_M, Key::KEY_N,
+    Key::KEY_O, Key::KEY_P, Key::KEY_Q, Key::KEY_R, Key::KEY_S, Key::KEY_T, Key::KEY_U,
+    Key::KEY_V, Key::KEY_W, Key::KEY_X, Key::KEY_Y, Key::KEY_Z,
+];
+
+/***** Discovery *****/
+
+/// Whether a device looks like a keyboard: it has the letter keys and autorepeat
+pub fn is_keyboard(device: &Device) -> bool {
+    let has_letters = device
+        .supported_keys()
+        .is_some_and(|keys| KEYBOARD_KEYS.iter().all(|key| keys.contains(*key)));
+    has_letters && device.supported_events().contains(EventType::REPEAT)
+}
+
+/// The `/dev/input/by-id` symlinks pointing at a device
+pub fn by_id_links(device_path: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(BY_ID_PATH) else {
+        return Vec::new();
+    };
+    let Ok(device_path) = fs::canonicalize(device_path) else {
+        return Vec::new();
+    };
+    let mut links = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|link| fs::canonicalize(link).is_ok_and(|target| target == device_path))
+        .collect::<Vec<_>>();
+    links.sort();
+    links
+}
+
+/// Whether a device is selected by a `[keyboard]` table: it is the table's
+/// device path, or it fits all of its match options. Without either, every
+/// keyboard is selected.
+pub fn matches(config: &KeyboardConfig, device_path: &Path, device: &Device) -> bool {
+    if let Some(configured_path) = &config.device {
+        return fs::canonicalize(configured_path)
+            .is_ok_and(|configured| fs::canonicalize(device_path).is_ok_and(|p| p == configured));
+    }
+    if let Some(name) = &config.name {
+        if !device.name().is_some_and(|n| n.contains(name.as_str())) {
+            return false;
+        }
+    }
+    if let Some(vendor) = config.vendor {
+        if device.input_id().vendor() != vendor {
+            return false;
+        }
+    }
+    if let Some(product) = config.product {
+        if device.input_id().product() != product {
+            return false;
+        }
+    }
+    if let Some(phys) = &config.phys {
+        if !device
+            .physical_path()
+            .is_some_and(|p| p.contains(phys.as_str()))
+        {
+            return false;
+        }
+    }
+    if let Some(by_id) = &config.by_id {
+        let by_id = Path::new(BY_ID_PATH).join(by_id); // Joining an absolute path replaces it
+        if !by_id_links(device_path).contains(&by_id) {
+            return false;
+        }
+    }
+    if !config.has_match_options() && !is_keyboard(device) {
+        return false;
+    }
+    true
+}
+
+/// Whether a device is selected by any of the `[keyboard]` tables
+pub fn matches_any(configs: &[KeyboardConfig], device_path: &Path, device: &Device) -> bool {
+    configs
+        .iter()
+        .any(|config| matches(config, device_path, device))
+}
+
+/// Whether the device at a path that just appeared should be grabbed
+pub fn path_matches(configs: &[KeyboardConfig], device_path: &Path) -> bool {
+    Device::open(device_path).is_ok_and(|device| matches_any(configs, device_path, &device))
+}
+
+/// Find every device to grab, sorted by path
+pub fn find_keyboards(configs: &[KeyboardConfig]) -> Vec<PathBuf> {
+    let mut keyboards = evdev::enumerate()
+        .filter(|(path, device)| {
+            let matched = matches_any(configs, path, device);
+            trace!(
+                "{} ({}) {}",
+                path.display(),
+                device.name().unwrap_or("<unnamed>"),
+                if matched { "matches" } else { "does not match" }
+            );
+            matched
+        })
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    keyboards.sort();
+    debug!("Discovered keyboards: {keyboards:?}");
+    keyboards
+}
+
+/// Find every device to grab, failing if there are none
+pub fn find_keyboards_or_fail(configs: &[KeyboardConfig]) -> Result<Vec<PathBuf>> {
+    let keyboards = find_keyboards(configs);
+    if !keyboards.is_empty() {
+        return Ok(keyboards);
+    }
+    if configs
+        .iter()
+        .any(|config| config.device.is_some() || config.has_match_options())
+    {
+        bail!("No input device matches the `[keyboard]` configuration")
+    }
+    bail!("No keyboard found in /dev/input (are you in the `input` group?)")
+}
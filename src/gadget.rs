@@ -0,0 +1,403 @@
+/*!
+ * Keyboard Bridge for Raspberry Pi - USB HID gadget
+ * Created by sheepy0125 on 2026-10-18 under the MIT license
+**/
+
+/***** Setup *****/
+use crate::config::{DisconnectedPolicy, GadgetConfig, ReportMode};
+use anyhow::{Context, Result};
+use log::{info, trace, warn};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    future,
+    io::{self, Read, Write},
+    os::unix::prelude::OpenOptionsExt,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::unix::AsyncFd,
+    time::{sleep, timeout},
+};
+// Constants
+pub const BOOT_REPORT_LENGTH: usize = 8_usize;
+/// The boot protocol report has 6 slots for regular keys
+pub const BOOT_REPORT_KEY_COUNT: usize = 6_usize;
+/// Fills every key slot when more keys are pressed than there are slots
+pub const ERROR_ROLL_OVER_USAGE: u8 = 0x01_u8;
+/// The NKRO report has a bit for each usage below the modifiers (0xE0)
+pub const NKRO_USAGE_COUNT: usize = 0xE0_usize;
+/// The modifiers, the reserved byte (as in the boot report), then the usage
+/// bitmap
+pub const NKRO_REPORT_LENGTH: usize = 2_usize + NKRO_USAGE_COUNT / 8;
+/// Consumer control reports are on the control gadget: [ID, usage (LE u16)]
+pub const CONSUMER_REPORT_ID: u8 = 0x01_u8;
+/// System control reports are on the control gadget: [ID, a bit per usage
+/// from power down (0x81) to wake up (0x83)]
+pub const SYSTEM_REPORT_ID: u8 = 0x02_u8;
+
+/***** Enums *****/
+/// How writing to the gadget is going, as of the last write
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GadgetHealth {
+    /// Reports are reaching the host
+    Healthy,
+    /// The host isn't listening: it is unplugged or the gadget isn't bound
+    /// (ESHUTDOWN, EBADF, ENODEV, EPIPE)
+    Disconnected,
+    /// The host hasn't read the previous report in time
+    Stalled,
+    /// Writing failed with an unexpected I/O error
+    Failing,
+}
+
+/***** Structs *****/
+/// Writer for a USB HID gadget device (`/dev/hidg0` for the keyboard,
+/// `/dev/hidg1` for the media and system keys) that waits for the host to be ready
+/// instead of busy-retrying
+pub struct Gadget {
+    path: PathBuf,
+    /// None after the host went away, until reopened
+    file: Option<AsyncFd<File>>,
+    health: GadgetHealth,
+    write_timeout: Duration,
+    reopen_interval: Duration,
+    last_open_attempt: Instant,
+    /// Whether the UDC says the host is there to read reports
+    host_connected: bool,
+    disconnected_policy: DisconnectedPolicy,
+    hold_limit: usize,
+    /// Reports held while the host is disconnected, oldest first
+    held_reports: VecDeque<Vec<u8>>,
+    /// The latest report of each kind whose write timed out, to write once
+    /// the host reads again (e.g. so a release isn't lost)
+    unsent_reports: Vec<Vec<u8>>,
+    /// The reports releasing every key this gadget can press
+    release_all_reports: Vec<Vec<u8>>,
+}
+impl Gadget {
+    pub fn open(
+        path: &Path,
+        config: &GadgetConfig,
+        release_all_reports: Vec<Vec<u8>>,
+    ) -> Result<Self> {
+        let file = open_gadget_file(path)
+            .with_context(|| format!("Open USB gadget file at {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Some(file),
+            health: GadgetHealth::Healthy,
+            write_timeout: config.write_timeout(),
+            reopen_interval: config.reopen_interval(),
+            last_open_attempt: Instant::now(),
+            host_connected: true,
+            disconnected_policy: config.while_disconnected,
+            hold_limit: config.hold_limit,
+            held_reports: VecDeque::new(),
+            unsent_reports: Vec::new(),
+            release_all_reports,
+        })
+    }
+
+    /// Release every key on the host. Returns whether the reports were written.
+    pub async fn release_all(&mut self) -> bool {
+        let mut written = true;
+        for report in self.release_all_reports.clone() {
+            written &= self.write_report(&report).await;
+        }
+        written
+    }
+
+    /// Update whether the host is connected. On reconnection, every key is
+    /// released and then any held reports are sent.
+    pub async fn set_host_connected(&mut self, host_connected: bool) {
+        let reconnected = host_connected && !self.host_connected;
+        self.host_connected = host_connected;
+        if !reconnected {
+            return;
+        }
+        self.release_all().await;
+        if !self.held_reports.is_empty() {
+            info!("Sending {} held reports.", self.held_reports.len());
+        }
+        while let Some(report) = self.held_reports.pop_front() {
+            self.write_report(&report).await;
+        }
+    }
+
+    /// Send a report to the host, or hold or drop it while the host is
+    /// disconnected
+    pub async fn send_report(&mut self, report: &[u8]) {
+        if self.host_connected {
+            self.write_report(report).await;
+            return;
+        }
+        match self.disconnected_policy {
+            DisconnectedPolicy::Drop => trace!("Host disconnected, dropping report {report:?}"),
+            DisconnectedPolicy::Hold => {
+                if self.held_reports.len() >= self.hold_limit {
+                    trace!("Too many held reports, dropping the oldest");
+                    self.held_reports.pop_front();
+                }
+                self.held_reports.push_back(report.to_vec());
+            }
+        }
+    }
+
+    /// Wait for the gadget to be writable again while there are reports
+    /// whose write timed out (see `write_unsent`). Never returns while there
+    /// are none.
+    pub async fn unsent_writable(&self) {
+        if self.unsent_reports.is_empty() || !self.host_connected {
+            return future::pending().await;
+        }
+        match &self.file {
+            Some(file) => {
+                if let Err(e) = file.writable().await {
+                    trace!("Waiting for the USB gadget to be writable failed: {e}");
+                    sleep(self.reopen_interval).await;
+                }
+            }
+            None => sleep(self.reopen_interval).await,
+        }
+    }
+
+    /// Write the reports whose write timed out, oldest first
+    pub async fn write_unsent(&mut self) {
+        for report in self.unsent_reports.clone() {
+            trace!("Retrying USB report {report:?}");
+            if !self.write_report(&report).await {
+                return;
+            }
+        }
+    }
+
+    pub fn health(&self) -> GadgetHealth {
+        self.health
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write_timeout(&self) -> Duration {
+        self.write_timeout
+    }
+
+    pub fn release_all_reports(&self) -> &[Vec<u8>] {
+        &self.release_all_reports
+    }
+
+    /// Log health transitions once rather than on every report
+    fn set_health(&mut self, health: GadgetHealth, reason: &dyn std::fmt::Display) {
+        use GadgetHealth::*;
+        if health == self.health {
+            trace!("USB gadget still {health:?}: {reason}");
+            return;
+        }
+        match health {
+            Healthy => info!("USB gadget is writable again ({reason})."),
+            Disconnected => warn!("USB host disconnected ({reason}). Dropping reports."),
+            Stalled => warn!(
+                "USB host stopped reading reports ({reason}). Sending the latest once it reads again."
+            ),
+            Failing => warn!("Writing to the USB gadget failed: {reason}. Dropping reports."),
+        }
+        self.health = health;
+    }
+
+    /// Reopen the gadget if it was closed, at most once per reopen interval
+    fn ensure_open(&mut self) -> bool {
+        if self.file.is_some() {
+            return true;
+        }
+        if self.last_open_attempt.elapsed() < self.reopen_interval {
+            return false;
+        }
+        self.last_open_attempt = Instant::now();
+        match open_gadget_file(&self.path) {
+            Ok(file) => {
+                info!("Reopened USB gadget at {}.", self.path.display());
+                self.file = Some(file);
+                true
+            }
+            Err(e) => {
+                trace!(
+                    "Reopening USB gadget at {} failed: {e}",
+                    self.path.display()
+                );
+                false
+            }
+        }
+    }
+
+    /// Write a report, waiting for the gadget to become writable for up to the
+    /// write timeout. Returns whether the report was written; failures are
+    /// reflected in the gadget's health, and a report timing out is kept for
+    /// `write_unsent`.
+    pub async fn write_report(&mut self, report: &[u8]) -> bool {
+        if !self.ensure_open() {
+            trace!("USB gadget closed, dropping report {report:?}");
+            return false;
+        }
+        let Some(file) = &self.file else {
+            return false;
+        };
+        trace!("Writing USB report {report:?}");
+        let result = timeout(self.write_timeout, write_when_ready(file, report)).await;
+        match result {
+            Ok(Ok(())) => {
+                self.set_health(GadgetHealth::Healthy, &"report written");
+                self.forget_unsent(report);
+                true
+            }
+            Ok(Err(e)) if is_disconnect(&e) => {
+                // The file may not be usable again (e.g. the gadget was rebound)
+                self.file = None;
+                self.last_open_attempt = Instant::now();
+                self.set_health(GadgetHealth::Disconnected, &e);
+                false
+            }
+            Ok(Err(e)) => {
+                self.set_health(GadgetHealth::Failing, &e);
+                false
+            }
+            Err(_elapsed) => {
+                self.forget_unsent(report);
+                self.unsent_reports.push(report.to_vec());
+                let write_timeout = self.write_timeout;
+                self.set_health(
+                    GadgetHealth::Stalled,
+                    &format_args!("nothing read for {write_timeout:?}"),
+                );
+                false
+            }
+        }
+    }
+
+    /// Forget the unsent report a report supersedes, being of the same kind.
+    /// With several kinds (the control gadget), the first byte is the report ID.
+    fn forget_unsent(&mut self, report: &[u8]) {
+        let single_kind = self.release_all_reports.len() <= 1;
+        (self.unsent_reports).retain(|unsent| !single_kind && unsent.first() != report.first());
+    }
+
+    /// Block until the host sends an LED output report, returning its LED bits
+    /// (num lock, caps lock, scroll lock, compose, kana from the lowest bit).
+    /// Blocks while the gadget is closed.
+    pub async fn read_leds(&self) -> u8 {
+        loop {
+            let Some(file) = &self.file else {
+                sleep(self.reopen_interval).await;
+                continue;
+            };
+            let mut report = [0_u8; 1];
+            match read_when_ready(file, &mut report).await {
+                Ok(1) => {
+                    trace!("USB LED report: {report:?}");
+                    return report[0];
+                }
+                Ok(read) => trace!("Ignoring USB output report of {read} bytes"),
+                Err(e) => {
+                    // Writes notice and report disconnections, so just back off
+                    trace!("Reading USB output report failed: {e}");
+                    sleep(self.reopen_interval).await;
+                }
+            }
+        }
+    }
+}
+
+/***** Auxiliary functions *****/
+
+/// A keyboard report with every key released
+pub fn release_all_report(report_mode: ReportMode) -> Vec<u8> {
+    match report_mode {
+        ReportMode::Boot => vec![0_u8; BOOT_REPORT_LENGTH],
+        ReportMode::Nkro => vec![0_u8; NKRO_REPORT_LENGTH],
+    }
+}
+
+/// The control gadget's reports with every key released
+pub fn release_all_control_reports() -> Vec<Vec<u8>> {
+    vec![
+        vec![CONSUMER_REPORT_ID, 0_u8, 0_u8],
+        vec![SYSTEM_REPORT_ID, 0_u8],
+    ]
+}
+
+fn open_gadget_file(path: &Path) -> io::Result<AsyncFd<File>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+    // SAFETY: The file owns its descriptor for as long as the AsyncFd owns the file
+    Ok(unsafe { AsyncFd::register(file) }?)
+}
+
+/// Whether a write error means the host isn't there, rather than a real I/O error
+fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ESHUTDOWN | libc::EBADF | libc::ENODEV | libc::EPIPE)
+    )
+}
+
+/// Write a whole report once the gadget is writable (the host has read the
+/// previous one)
+async fn write_when_ready(file: &AsyncFd<File>, report: &[u8]) -> io::Result<()> {
+    loop {
+        let mut guard = file.writable().await?;
+        match guard.try_io(|file| file.get_ref().write(report)) {
+            Ok(Ok(written)) if written == report.len() => return Ok(()),
+            Ok(Ok(written)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    format!("only {written} of {} bytes written", report.len()),
+                ))
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+/// Read a report once the host has sent one
+async fn read_when_ready(file: &AsyncFd<File>, report: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let mut guard = file.readable().await?;
+        match guard.try_io(|file| file.get_ref().read(report)) {
+            Ok(result) => return result,
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+/// Synchronously send the reports with every key released through a fresh
+/// file handle, for when the async writer can't be used (i.e. while panicking)
+pub fn release_all_keys_blocking(path: &Path, write_timeout: Duration, reports: &[Vec<u8>]) {
+    let start = Instant::now();
+    let result = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .and_then(|mut file| {
+            reports.iter().try_for_each(|report| loop {
+                match file.write(report) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        if start.elapsed() >= write_timeout {
+                            return Err(e);
+                        }
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    result => return result.map(|_| ()),
+                }
+            })
+        });
+    if let Err(e) = result {
+        warn!("Failed to release all keys: {e}. Keys may be stuck on the host.");
+    }
+}
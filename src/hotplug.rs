@@ -0,0 +1,92 @@
+/*!
+ * Keyboard Bridge for Raspberry Pi - Hot-plugging
+ * Created by sheepy0125 on 2026-10-18 under the MIT license
+**/
+
+/***** Setup *****/
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use inotify::{EventMask, EventStream, Inotify, WatchDescriptor, WatchMask};
+use log::{debug, trace};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+// Constants
+const INPUT_PATH: &str = "/dev/input";
+const BY_ID_DIRECTORY_NAME: &str = "by-id";
+const EVENT_BUFFER_SIZE: usize = 4096_usize;
+
+/***** Structs *****/
+/// Watches `/dev/input` for devices being plugged in
+pub struct DeviceWatcher {
+    events: EventStream<[u8; EVENT_BUFFER_SIZE]>,
+    input_watch: WatchDescriptor,
+    /// `/dev/input/by-id` only exists while there are devices with an ID
+    by_id_watch: Option<WatchDescriptor>,
+}
+impl DeviceWatcher {
+    pub fn new() -> Result<Self> {
+        let inotify = Inotify::init().context("Initialize inotify")?;
+        // udev creates the device nodes root-only, then fixes their permissions
+        let input_watch = inotify
+            .watches()
+            .add(INPUT_PATH, WatchMask::CREATE | WatchMask::ATTRIB)
+            .with_context(|| format!("Watch {INPUT_PATH}"))?;
+        let events = inotify
+            .into_event_stream([0_u8; EVENT_BUFFER_SIZE])
+            .context("Get inotify event stream")?;
+        let mut watcher = Self {
+            events,
+            input_watch,
+            by_id_watch: None,
+        };
+        watcher.watch_by_id();
+        Ok(watcher)
+    }
+
+    /// Start watching `/dev/input/by-id`, as its symlinks only appear after
+    /// the device nodes
+    fn watch_by_id(&mut self) {
+        let by_id_path = Path::new(INPUT_PATH).join(BY_ID_DIRECTORY_NAME);
+        match self.events.watches().add(&by_id_path, WatchMask::CREATE) {
+            Ok(by_id_watch) => self.by_id_watch = Some(by_id_watch),
+            Err(e) => debug!("Not watching {} yet: {e}", by_id_path.display()),
+        }
+    }
+
+    /// Block until a device node is created or changed, returning its path
+    pub async fn next_device(&mut self) -> Result<PathBuf> {
+        loop {
+            let event = self
+                .events
+                .next()
+                .await
+                .context("inotify event stream ended")?
+                .context("Read inotify event")?;
+            let Some(name) = event.name else {
+                continue;
+            };
+            trace!("inotify {:?} for {name:?}", event.mask);
+
+            if event.wd == self.input_watch {
+                if event.mask.contains(EventMask::ISDIR) {
+                    if name == BY_ID_DIRECTORY_NAME {
+                        self.watch_by_id();
+                    }
+                    continue;
+                }
+                if !name.to_string_lossy().starts_with("event") {
+                    continue;
+                }
+                return Ok(Path::new(INPUT_PATH).join(name));
+            }
+            if Some(&event.wd) == self.by_id_watch.as_ref() {
+                let link = Path::new(INPUT_PATH).join(BY_ID_DIRECTORY_NAME).join(name);
+                if let Ok(device_path) = fs::canonicalize(link) {
+                    return Ok(device_path);
+                }
+            }
+        }
+    }
+}
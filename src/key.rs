@@ -4,18 +4,26 @@
 **/
 
 /***** Setup *****/
-use evdev::InputEvent;
-use {KeyCode::*, ModifierKey::*, RegularKey::*};
+use anyhow::{anyhow, Error};
+use evdev::{InputEvent, Key};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserializer,
+};
+use std::{fmt, marker::PhantomData, ops::Deref, str::FromStr};
+use {ConsumerKey::*, KeyCode::*, ModifierKey::*, RegularKey::*, SystemKey::*};
 
 /***** USB Key codes *****/
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum KeyCode {
     Regular(RegularKey),
     Modifier(ModifierKey),
+    Consumer(ConsumerKey),
+    System(SystemKey),
     Unknown,
 }
 #[repr(u8)]
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum RegularKey {
     Empty = 0x00,
     A = 0x04,
@@ -64,6 +72,7 @@ pub enum RegularKey {
     LeftSquareBracket = 0x2F,
     RightSquareBracket = 0x30,
     BackSlash = 0x31,
+    NonUsHash = 0x32,
     Semicolon = 0x33,
     SingleQuote = 0x34,
     Grave = 0x35,
@@ -85,6 +94,7 @@ pub enum RegularKey {
     F12 = 0x45,
     PrintScreen = 0x46,
     ScrollLock = 0x47,
+    Pause = 0x48,
     Insert = 0x49,
     Home = 0x4A,
     PageUp = 0x4B,
@@ -112,18 +122,121 @@ pub enum RegularKey {
     KeyPadNum9 = 0x61,
     KeyPadNum0 = 0x62,
     KeyPadPeriod = 0x63,
+    NonUsBackslash = 0x64,
+    Application = 0x65,
     Power = 0x66,
     KeyPadEqual = 0x67,
+    F13 = 0x68,
+    F14 = 0x69,
+    F15 = 0x6A,
+    F16 = 0x6B,
+    F17 = 0x6C,
+    F18 = 0x6D,
+    F19 = 0x6E,
+    F20 = 0x6F,
+    F21 = 0x70,
+    F22 = 0x71,
+    F23 = 0x72,
+    F24 = 0x73,
+    Execute = 0x74,
+    Help = 0x75,
+    Menu = 0x76,
+    Select = 0x77,
+    Stop = 0x78,
+    Again = 0x79,
+    Undo = 0x7A,
+    Cut = 0x7B,
+    Copy = 0x7C,
+    Paste = 0x7D,
+    Find = 0x7E,
     VolumeMute = 0x7F,
     VolumeUp = 0x80,
     VolumeDown = 0x81,
+    LockingCapsLock = 0x82,
+    LockingNumLock = 0x83,
+    LockingScrollLock = 0x84,
     KeyPadComma = 0x85,
+    KeyPadEqualSign = 0x86,
+    International1 = 0x87,
+    International2 = 0x88,
+    International3 = 0x89,
+    International4 = 0x8A,
+    International5 = 0x8B,
+    International6 = 0x8C,
+    International7 = 0x8D,
+    International8 = 0x8E,
+    International9 = 0x8F,
+    Lang1 = 0x90,
+    Lang2 = 0x91,
+    Lang3 = 0x92,
+    Lang4 = 0x93,
+    Lang5 = 0x94,
+    Lang6 = 0x95,
+    Lang7 = 0x96,
+    Lang8 = 0x97,
+    Lang9 = 0x98,
+    AlternateErase = 0x99,
+    SysReq = 0x9A,
+    Cancel = 0x9B,
+    Clear = 0x9C,
+    Prior = 0x9D,
+    Return = 0x9E,
+    Separator = 0x9F,
+    Out = 0xA0,
+    Oper = 0xA1,
+    ClearAgain = 0xA2,
+    CrSel = 0xA3,
+    ExSel = 0xA4,
+    KeyPadNum00 = 0xB0,
+    KeyPadNum000 = 0xB1,
+    ThousandsSeparator = 0xB2,
+    DecimalSeparator = 0xB3,
+    CurrencyUnit = 0xB4,
+    CurrencySubUnit = 0xB5,
     KeyPadLeftParen = 0xB6,
     KeyPadRightParen = 0xB7,
+    KeyPadLeftBrace = 0xB8,
+    KeyPadRightBrace = 0xB9,
+    KeyPadTab = 0xBA,
+    KeyPadBackspace = 0xBB,
+    KeyPadA = 0xBC,
+    KeyPadB = 0xBD,
+    KeyPadC = 0xBE,
+    KeyPadD = 0xBF,
+    KeyPadE = 0xC0,
+    KeyPadF = 0xC1,
+    KeyPadXor = 0xC2,
+    KeyPadCaret = 0xC3,
+    KeyPadPercent = 0xC4,
+    KeyPadLessThan = 0xC5,
+    KeyPadGreaterThan = 0xC6,
+    KeyPadAmpersand = 0xC7,
+    KeyPadDoubleAmpersand = 0xC8,
+    KeyPadPipe = 0xC9,
+    KeyPadDoublePipe = 0xCA,
+    KeyPadColon = 0xCB,
+    KeyPadHash = 0xCC,
+    KeyPadSpace = 0xCD,
+    KeyPadAt = 0xCE,
+    KeyPadExclamation = 0xCF,
+    KeyPadMemoryStore = 0xD0,
+    KeyPadMemoryRecall = 0xD1,
+    KeyPadMemoryClear = 0xD2,
+    KeyPadMemoryAdd = 0xD3,
+    KeyPadMemorySubtract = 0xD4,
+    KeyPadMemoryMultiply = 0xD5,
+    KeyPadMemoryDivide = 0xD6,
+    KeyPadPlusMinus = 0xD7,
+    KeyPadClear = 0xD8,
+    KeyPadClearEntry = 0xD9,
+    KeyPadBinary = 0xDA,
+    KeyPadOctal = 0xDB,
+    KeyPadDecimal = 0xDC,
+    KeyPadHexadecimal = 0xDD,
 }
 /// Masks for the modifier keys (left-most bit)
 #[repr(u8)]
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 #[rustfmt::skip]
 pub enum ModifierKey {
     LeftCtrl =   0b00000001,
@@ -134,11 +247,586 @@ pub enum ModifierKey {
     RightShift = 0b00100000,
     RightAlt =   0b01000000,
     RightSuper = 0b10000000,
-    /* Special modifier keys for chords */
-    EitherCtrl =  0xFC,
-    EitherShift = 0xFD,
-    EitherAlt =   0xFE,
-    EitherSuper = 0xFF
+}
+/// Consumer page usages (media keys), sent in their own report
+#[repr(u16)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ConsumerKey {
+    BrightnessUp = 0x006F,
+    BrightnessDown = 0x0070,
+    MediaPlay = 0x00B0,
+    MediaPause = 0x00B1,
+    Record = 0x00B2,
+    FastForward = 0x00B3,
+    Rewind = 0x00B4,
+    NextTrack = 0x00B5,
+    PreviousTrack = 0x00B6,
+    MediaStop = 0x00B7,
+    Eject = 0x00B8,
+    PlayPause = 0x00CD,
+    Mute = 0x00E2,
+    VolumeIncrement = 0x00E9,
+    VolumeDecrement = 0x00EA,
+    MediaSelect = 0x0183,
+    Mail = 0x018A,
+    Calculator = 0x0192,
+    MyComputer = 0x0194,
+    Browser = 0x0196,
+    BrowserSearch = 0x0221,
+    BrowserHome = 0x0223,
+    BrowserBack = 0x0224,
+    BrowserForward = 0x0225,
+    BrowserStop = 0x0226,
+    BrowserRefresh = 0x0227,
+    BrowserBookmarks = 0x022A,
+}
+/// Generic Desktop page system control usages, sent in their own report
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SystemKey {
+    PowerDown = 0x81,
+    Sleep = 0x82,
+    WakeUp = 0x83,
+}
+
+/***** Key names *****/
+impl RegularKey {
+    /// Every regular key, in usage ID order
+    #[rustfmt::skip]
+    pub const ALL: &'static [RegularKey] = &[
+        Empty, A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Num1,
+        Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, Num0, Enter, Escape, Backspace, Tab, Space,
+        Minus, Equals, LeftSquareBracket, RightSquareBracket, BackSlash, NonUsHash, Semicolon,
+        SingleQuote, Grave, Comma, Period, ForwardSlash, CapsLock, F1, F2, F3, F4, F5, F6, F7, F8,
+        F9, F10, F11, F12, PrintScreen, ScrollLock, Pause, Insert, Home, PageUp, Delete, End,
+        PageDown, Right, Left, Down, Up, NumLock, KeyPadSlash, KeyPadAsterisk, KeyPadMinus,
+        KeyPadPlus, KeyPadEnter, KeyPadNum1, KeyPadNum2, KeyPadNum3, KeyPadNum4, KeyPadNum5,
+        KeyPadNum6, KeyPadNum7, KeyPadNum8, KeyPadNum9, KeyPadNum0, KeyPadPeriod, NonUsBackslash,
+        Application, Power, KeyPadEqual, F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24,
+        Execute, Help, Menu, Select, Stop, Again, Undo, Cut, Copy, Paste, Find, VolumeMute,
+        VolumeUp, VolumeDown, LockingCapsLock, LockingNumLock, LockingScrollLock, KeyPadComma,
+        KeyPadEqualSign, International1, International2, International3, International4,
+        International5, International6, International7, International8, International9, Lang1,
+        Lang2, Lang3, Lang4, Lang5, Lang6, Lang7, Lang8, Lang9, AlternateErase, SysReq, Cancel,
+        Clear, Prior, Return, Separator, Out, Oper, ClearAgain, CrSel, ExSel, KeyPadNum00,
+        KeyPadNum000, ThousandsSeparator, DecimalSeparator, CurrencyUnit, CurrencySubUnit,
+        KeyPadLeftParen, KeyPadRightParen, KeyPadLeftBrace, KeyPadRightBrace, KeyPadTab,
+        KeyPadBackspace, KeyPadA, KeyPadB, KeyPadC, KeyPadD, KeyPadE, KeyPadF, KeyPadXor,
+        KeyPadCaret, KeyPadPercent, KeyPadLessThan, KeyPadGreaterThan, KeyPadAmpersand,
+        KeyPadDoubleAmpersand, KeyPadPipe, KeyPadDoublePipe, KeyPadColon, KeyPadHash, KeyPadSpace,
+        KeyPadAt, KeyPadExclamation, KeyPadMemoryStore, KeyPadMemoryRecall, KeyPadMemoryClear,
+        KeyPadMemoryAdd, KeyPadMemorySubtract, KeyPadMemoryMultiply, KeyPadMemoryDivide,
+        KeyPadPlusMinus, KeyPadClear, KeyPadClearEntry, KeyPadBinary, KeyPadOctal, KeyPadDecimal,
+        KeyPadHexadecimal,
+    ];
+}
+impl ModifierKey {
+    /// Every modifier key, in bit order
+    #[rustfmt::skip]
+    pub const ALL: &'static [ModifierKey] = &[
+        LeftCtrl, LeftShift, LeftAlt, LeftSuper, RightCtrl, RightShift, RightAlt, RightSuper,
+    ];
+}
+impl ConsumerKey {
+    /// Every consumer key, in usage ID order
+    #[rustfmt::skip]
+    pub const ALL: &'static [ConsumerKey] = &[
+        BrightnessUp, BrightnessDown, MediaPlay, MediaPause, Record, FastForward, Rewind, NextTrack,
+        PreviousTrack, MediaStop, Eject, PlayPause, Mute, VolumeIncrement, VolumeDecrement,
+        MediaSelect, Mail, Calculator, MyComputer, Browser, BrowserSearch, BrowserHome, BrowserBack,
+        BrowserForward, BrowserStop, BrowserRefresh, BrowserBookmarks,
+    ];
+}
+impl SystemKey {
+    /// Every system key, in usage ID order
+    pub const ALL: &'static [SystemKey] = &[PowerDown, Sleep, WakeUp];
+}
+impl KeyCode {
+    /// Every key, in the order of the `ALL` lists
+    pub fn all() -> impl Iterator<Item = KeyCode> {
+        RegularKey::ALL
+            .iter()
+            .map(|k| Regular(*k))
+            .chain(ModifierKey::ALL.iter().map(|k| Modifier(*k)))
+            .chain(ConsumerKey::ALL.iter().map(|k| Consumer(*k)))
+            .chain(SystemKey::ALL.iter().map(|k| System(*k)))
+    }
+
+    /// The key's friendly names, if it has any. The first is the one it's
+    /// displayed as.
+    fn names(self) -> &'static [&'static str] {
+        match self {
+            Regular(regular_key) => regular_key.names(),
+            Modifier(modifier_key) => modifier_key.names(),
+            Consumer(_) | System(_) | Unknown => &[],
+        }
+    }
+
+    /// The key's variant name (e.g. `LeftCtrl`)
+    fn variant_name(self) -> String {
+        match self {
+            Regular(regular_key) => format!("{regular_key:?}"),
+            Modifier(modifier_key) => format!("{modifier_key:?}"),
+            Consumer(consumer_key) => format!("{consumer_key:?}"),
+            System(system_key) => format!("{system_key:?}"),
+            Unknown => "Unknown".into(),
+        }
+    }
+}
+impl RegularKey {
+    /// The characters the key types on a US layout, unshifted and shifted
+    pub fn characters(self) -> Option<(char, char)> {
+        let characters = match self {
+            A => ('a', 'A'),
+            B => ('b', 'B'),
+            C => ('c', 'C'),
+            D => ('d', 'D'),
+            E => ('e', 'E'),
+            F => ('f', 'F'),
+            G => ('g', 'G'),
+            H => ('h', 'H'),
+            I => ('i', 'I'),
+            J => ('j', 'J'),
+            K => ('k', 'K'),
+            L => ('l', 'L'),
+            M => ('m', 'M'),
+            N => ('n', 'N'),
+            O => ('o', 'O'),
+            P => ('p', 'P'),
+            Q => ('q', 'Q'),
+            R => ('r', 'R'),
+            S => ('s', 'S'),
+            T => ('t', 'T'),
+            U => ('u', 'U'),
+            V => ('v', 'V'),
+            W => ('w', 'W'),
+            X => ('x', 'X'),
+            Y => ('y', 'Y'),
+            Z => ('z', 'Z'),
+            Num1 => ('1', '!'),
+            Num2 => ('2', '@'),
+            Num3 => ('3', '#'),
+            Num4 => ('4', '$'),
+            Num5 => ('5', '%'),
+            Num6 => ('6', '^'),
+            Num7 => ('7', '&'),
+            Num8 => ('8', '*'),
+            Num9 => ('9', '('),
+            Num0 => ('0', ')'),
+            Minus => ('-', '_'),
+            Equals => ('=', '+'),
+            LeftSquareBracket => ('[', '{'),
+            RightSquareBracket => (']', '}'),
+            BackSlash => ('\\', '|'),
+            Semicolon => (';', ':'),
+            SingleQuote => ('\'', '"'),
+            Grave => ('`', '~'),
+            Comma => (',', '<'),
+            Period => ('.', '>'),
+            ForwardSlash => ('/', '?'),
+            _ => return None,
+        };
+        Some(characters)
+    }
+
+    /// The key typing a character on a US layout, and whether it's shifted
+    pub fn from_char(character: char) -> Option<(RegularKey, bool)> {
+        RegularKey::ALL
+            .iter()
+            .find_map(|key| match key.characters()? {
+                (unshifted, _) if unshifted == character => Some((*key, false)),
+                (_, shifted) if shifted == character => Some((*key, true)),
+                _ => None,
+            })
+    }
+
+    /// The key typing a character, including whitespace, and whether it's
+    /// shifted
+    pub fn from_typed_char(character: char) -> Option<(RegularKey, bool)> {
+        match character {
+            ' ' => Some((Space, false)),
+            '\t' => Some((Tab, false)),
+            '\n' => Some((Enter, false)),
+            character => RegularKey::from_char(character),
+        }
+    }
+
+    /// The key's friendly names besides its characters and variant name. The
+    /// first is the one it's displayed as.
+    #[rustfmt::skip]
+    fn names(self) -> &'static [&'static str] {
+        match self {
+            Equals => &["Equal"],
+            LeftSquareBracket => &["LBracket"],
+            RightSquareBracket => &["RBracket"],
+            SingleQuote => &["Quote", "Apostrophe"],
+            Grave => &["Backtick"],
+            Period => &["Dot"],
+            ForwardSlash => &["Slash"],
+            Enter => &["Enter", "CR"],
+            Escape => &["Esc"],
+            Backspace => &["BS"],
+            Space => &["Space", "Spc"],
+            CapsLock => &["CapsLock", "Caps"],
+            PrintScreen => &["PrintScreen", "PrtSc", "SysRq"],
+            ScrollLock => &["ScrollLock", "ScrLk"],
+            Pause => &["Pause", "Break"],
+            Insert => &["Insert", "Ins"],
+            Delete => &["Del"],
+            PageUp => &["PageUp", "PgUp"],
+            PageDown => &["PageDown", "PgDn"],
+            NumLock => &["NumLock", "NumLk"],
+            KeyPadSlash => &["kp/"],
+            KeyPadAsterisk => &["kp*"],
+            KeyPadMinus => &["kp-"],
+            KeyPadPlus => &["kp+"],
+            KeyPadEnter => &["kpEnter"],
+            KeyPadNum1 => &["kp1"],
+            KeyPadNum2 => &["kp2"],
+            KeyPadNum3 => &["kp3"],
+            KeyPadNum4 => &["kp4"],
+            KeyPadNum5 => &["kp5"],
+            KeyPadNum6 => &["kp6"],
+            KeyPadNum7 => &["kp7"],
+            KeyPadNum8 => &["kp8"],
+            KeyPadNum9 => &["kp9"],
+            KeyPadNum0 => &["kp0"],
+            KeyPadPeriod => &["kp."],
+            KeyPadEqual => &["kp="],
+            KeyPadComma => &["kp,"],
+            KeyPadLeftParen => &["kp("],
+            KeyPadRightParen => &["kp)"],
+            KeyPadNum00 => &["kp00"],
+            KeyPadNum000 => &["kp000"],
+            NonUsBackslash => &["NonUsBackslash", "102nd"],
+            Application => &["App", "Compose"],
+            _ => &[],
+        }
+    }
+}
+impl ModifierKey {
+    /// The key's friendly names besides its variant name. The first is the one
+    /// it's displayed as.
+    #[rustfmt::skip]
+    fn names(self) -> &'static [&'static str] {
+        match self {
+            LeftCtrl => &["LCtrl", "LCtl"],
+            LeftShift => &["LShift", "LSft"],
+            LeftAlt => &["LAlt"],
+            LeftSuper => &["LSuper", "LMeta", "LGui", "LWin"],
+            RightCtrl => &["RCtrl", "RCtl"],
+            RightShift => &["RShift", "RSft"],
+            RightAlt => &["RAlt", "AltGr"],
+            RightSuper => &["RSuper", "RMeta", "RGui", "RWin"],
+        }
+    }
+}
+
+/// Parse a key from a character it types (e.g. `~`), a friendly name (e.g.
+/// `enter`, `lshift`, `kp+`) or its variant name (e.g. `LeftShift`,
+/// `left-shift`), ignoring case. The name may be in angle
+/// brackets as in sequence notation.
+impl FromStr for KeyCode {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = unbracketed_name(name);
+        let mut characters = name.chars();
+        if let (Some(character), None) = (characters.next(), characters.next()) {
+            if let Some((regular_key, _shifted)) = RegularKey::from_char(character) {
+                return Ok(Regular(regular_key));
+            }
+        }
+        if let Some(key_code) =
+            KeyCode::all().find(|k| k.names().iter().any(|n| n.eq_ignore_ascii_case(name)))
+        {
+            return Ok(key_code);
+        }
+        if let Some(key_code) = KeyCode::all().find(|k| {
+            let variant_name = k.variant_name();
+            variant_name.eq_ignore_ascii_case(name)
+                || kebab_case(&variant_name).eq_ignore_ascii_case(name)
+        }) {
+            return Ok(key_code);
+        }
+        Err(anyhow!("Unknown key name `{name}`"))
+    }
+}
+/// A key's character, or else its friendly name, or else its variant name
+impl fmt::Display for KeyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Regular(regular_key) = self {
+            if let Some((character, _shifted)) = regular_key.characters() {
+                return write!(f, "{character}");
+            }
+        }
+        match self.names().first() {
+            Some(name) => f.write_str(name),
+            None => f.write_str(&self.variant_name()),
+        }
+    }
+}
+impl fmt::Display for RegularKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Regular(*self).fmt(f)
+    }
+}
+impl fmt::Display for ModifierKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Modifier(*self).fmt(f)
+    }
+}
+impl FromStr for RegularKey {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.parse()? {
+            Regular(regular_key) => Ok(regular_key),
+            key_code => Err(anyhow!("`{name}` is {key_code}, not a regular key")),
+        }
+    }
+}
+impl FromStr for ModifierKey {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.parse()? {
+            Modifier(modifier_key) => Ok(modifier_key),
+            key_code => Err(anyhow!("`{name}` is {key_code}, not a modifier key")),
+        }
+    }
+}
+
+/***** Key sequences *****/
+/// Keys in sequence notation: a character stands for the key typing it
+/// (after left shift if it's shifted) and `<Name>` for any key by name,
+/// e.g. `<LCtrl> <LAlt> <Del>`. Whitespace between keys is ignored.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct KeySequence(pub Vec<KeyCode>);
+impl Deref for KeySequence {
+    type Target = [KeyCode];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl FromIterator<KeyCode> for KeySequence {
+    fn from_iter<T: IntoIterator<Item = KeyCode>>(keys: T) -> Self {
+        Self(keys.into_iter().collect())
+    }
+}
+impl FromStr for KeySequence {
+    type Err = Error;
+
+    fn from_str(notation: &str) -> Result<Self, Self::Err> {
+        parse_sequence(notation, Modifier(LeftShift))
+    }
+}
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_sequence(f, self, &Modifier(LeftShift), |key_code| match key_code {
+            Regular(regular_key) => Some(*regular_key),
+            _ => None,
+        })
+    }
+}
+
+/// A key in sequence notation
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SequenceToken<'a> {
+    /// `<Name>`, without the angle brackets
+    Name(&'a str),
+    /// A character, as the key typing it and whether it's shifted
+    Character(RegularKey, bool),
+}
+
+/// Split sequence notation into its keys
+pub fn sequence_tokens(notation: &str) -> Result<Vec<SequenceToken<'_>>, Error> {
+    let mut tokens = Vec::new();
+    let mut rest = notation;
+    while let Some(character) = rest.chars().next() {
+        // `<` is only a name's start if a name without whitespace follows
+        if character == '<' {
+            if let Some(name) = rest[1..]
+                .split_once('>')
+                .map(|(name, _)| name)
+                .filter(|name| {
+                    !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '<')
+                })
+            {
+                tokens.push(SequenceToken::Name(name));
+                rest = &rest[name.len() + 2..];
+                continue;
+            }
+        }
+        rest = &rest[character.len_utf8()..];
+        if character.is_whitespace() {
+            continue;
+        }
+        let (regular_key, shifted) = RegularKey::from_char(character)
+            .ok_or_else(|| anyhow!("No key types `{character}`, use `<Name>`"))?;
+        tokens.push(SequenceToken::Character(regular_key, shifted));
+    }
+    Ok(tokens)
+}
+
+/// Parse sequence notation into keys or patterns, `shift` being the one
+/// before a shifted character's key
+pub fn parse_sequence<S, T>(notation: &str, shift: T) -> Result<S, Error>
+where
+    S: FromIterator<T>,
+    T: FromStr<Err = Error> + From<KeyCode> + Clone,
+{
+    let mut items = Vec::new();
+    for token in sequence_tokens(notation)? {
+        match token {
+            SequenceToken::Name(name) => items.push(name.parse()?),
+            SequenceToken::Character(regular_key, shifted) => {
+                if shifted {
+                    items.push(shift.clone());
+                }
+                items.push(Regular(regular_key).into());
+            }
+        }
+    }
+    Ok(items.into_iter().collect())
+}
+
+/// Write keys or patterns in sequence notation, as characters where a key
+/// types one and `shift` before a key as its shifted character
+pub fn fmt_sequence<T: PartialEq + fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    items: &[T],
+    shift: &T,
+    regular_key: impl Fn(&T) -> Option<RegularKey>,
+) -> fmt::Result {
+    let characters = |item: Option<&T>| regular_key(item?)?.characters();
+    let mut tokens = Vec::new();
+    let mut items = items.iter().peekable();
+    while let Some(item) = items.next() {
+        if item == shift {
+            if let Some((_, shifted)) = characters(items.peek().copied()) {
+                tokens.push(shifted.to_string());
+                items.next();
+                continue;
+            }
+        }
+        match characters(Some(item)) {
+            Some((unshifted, _)) => tokens.push(unshifted.to_string()),
+            None => tokens.push(format!("<{item}>")),
+        }
+    }
+    f.write_str(&tokens.join(" "))
+}
+
+/// A name without the angle brackets it may be in, as in sequence notation
+pub fn unbracketed_name(name: &str) -> &str {
+    let name = name.trim();
+    (name.strip_prefix('<'))
+        .and_then(|name| name.strip_suffix('>'))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(name)
+}
+
+/***** Serialization *****/
+/* Keys are (de)serialized by name, as they're displayed and parsed, so files
+ * stay readable and don't depend on the enums' order or discriminants.
+**/
+/// Deserialize a sequence from notation or an array of names, as written in
+/// the configuration file. Always built, as the configuration needs it.
+pub fn deserialize_sequence<'de, D, S, T>(
+    deserializer: D,
+    expecting: &'static str,
+) -> Result<S, D::Error>
+where
+    D: Deserializer<'de>,
+    S: FromStr<Err = Error> + FromIterator<T>,
+    T: FromStr<Err = Error>,
+{
+    struct SequenceVisitor<S, T> {
+        expecting: &'static str,
+        sequence: PhantomData<(S, T)>,
+    }
+    impl<'de, S, T> Visitor<'de> for SequenceVisitor<S, T>
+    where
+        S: FromStr<Err = Error> + FromIterator<T>,
+        T: FromStr<Err = Error>,
+    {
+        type Value = S;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str(self.expecting)
+        }
+
+        fn visit_str<E: de::Error>(self, notation: &str) -> Result<Self::Value, E> {
+            notation.parse().map_err(E::custom)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut items = Vec::new();
+            while let Some(name) = seq.next_element::<String>()? {
+                items.push(name.parse().map_err(de::Error::custom)?);
+            }
+            Ok(items.into_iter().collect())
+        }
+    }
+    deserializer.deserialize_any(SequenceVisitor {
+        expecting,
+        sequence: PhantomData,
+    })
+}
+
+#[cfg(feature = "key-serde")]
+mod key_serde {
+    use super::*;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    macro_rules! serde_by_name {
+        ($($key_type:ty),*) => {$(
+            impl Serialize for $key_type {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.collect_str(self)
+                }
+            }
+            impl<'de> Deserialize<'de> for $key_type {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let name = String::deserialize(deserializer)?;
+                    name.parse().map_err(de::Error::custom)
+                }
+            }
+        )*};
+    }
+    serde_by_name!(KeyCode, RegularKey, ModifierKey);
+
+    /// In sequence notation
+    impl Serialize for KeySequence {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+    /// From sequence notation or an array of key names
+    impl<'de> Deserialize<'de> for KeySequence {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserialize_sequence::<_, _, KeyCode>(
+                deserializer,
+                "a key sequence or an array of key names",
+            )
+        }
+    }
+}
+
+/***** Auxiliary functions *****/
+
+/// `LeftCtrl` to `left-ctrl`
+fn kebab_case(name: &str) -> String {
+    let mut kebab_case = String::new();
+    for (idx, character) in name.char_indices() {
+        if character.is_ascii_uppercase() && idx > 0 {
+            kebab_case.push('-');
+        }
+        kebab_case.push(character.to_ascii_lowercase());
+    }
+    kebab_case
 }
 
 /***** Linux /dev/input keycodes to USB keycode lookup table *****/
@@ -189,7 +877,7 @@ impl From<InputEvent> for KeyCode {
             /* KEY_APOSTROPHE */ 40 => Regular(SingleQuote),
             /* KEY_GRAVE */ 41 => Regular(Grave),
             /* KEY_LEFTSHIFT */ 42 => Modifier(LeftShift),
-            /* KEY_BACKSLASH */ 43 => Regular(BackSlash),
+            /* KEY_BACKSLASH */ 43 => Regular(BackSlash), /* Also non-US `#` */
             /* KEY_Z */ 44 => Regular(Z),
             /* KEY_X */ 45 => Regular(X),
             /* KEY_C */ 46 => Regular(C),
@@ -230,9 +918,17 @@ impl From<InputEvent> for KeyCode {
             /* KEY_KP3 */ 81 => Regular(KeyPadNum3),
             /* KEY_KP0 */ 82 => Regular(KeyPadNum0),
             /* KEY_KPDOT */ 83 => Regular(KeyPadPeriod),
-            /* KEY_102ND */ 86 => Regular(BackSlash), /* `\` for non-US */
+            /* KEY_ZENKAKUHANKAKU */ 85 => Regular(Lang5),
+            /* KEY_102ND */ 86 => Regular(NonUsBackslash),
             /* KEY_F11 */ 87 => Regular(F11),
             /* KEY_F12 */ 88 => Regular(F12),
+            /* KEY_RO */ 89 => Regular(International1),
+            /* KEY_KATAKANA */ 90 => Regular(Lang3),
+            /* KEY_HIRAGANA */ 91 => Regular(Lang4),
+            /* KEY_HENKAN */ 92 => Regular(International4),
+            /* KEY_KATAKANAHIRAGANA */ 93 => Regular(International2),
+            /* KEY_MUHENKAN */ 94 => Regular(International5),
+            /* KEY_KPJPCOMMA */ 95 => Regular(International6),
             /* KEY_KPENTER */ 96 => Regular(KeyPadEnter),
             /* KEY_RIGHTCTRL */ 97 => Modifier(RightCtrl),
             /* KEY_KPSLASH */ 98 => Regular(KeyPadSlash),
@@ -248,25 +944,441 @@ impl From<InputEvent> for KeyCode {
             /* KEY_PAGEDOWN */ 109 => Regular(PageDown),
             /* KEY_INSERT */ 110 => Regular(Insert),
             /* KEY_DELETE */ 111 => Regular(Delete),
-            /* KEY_MUTE */ 113 => Regular(VolumeMute),
-            /* KEY_VOLUMEDOWN */ 114 => Regular(VolumeDown),
-            /* KEY_VOLUMEUP */ 115 => Regular(VolumeUp),
-            /* KEY_POWER */ 116 => Regular(Power),
+            /* KEY_MUTE */ 113 => Consumer(Mute),
+            /* KEY_VOLUMEDOWN */ 114 => Consumer(VolumeDecrement),
+            /* KEY_VOLUMEUP */ 115 => Consumer(VolumeIncrement),
+            /* KEY_POWER */ 116 => System(PowerDown),
             /* KEY_KPEQUAL */ 117 => Regular(KeyPadEqual),
-            /* KEY_KPPLUSMINUS */ 118 => Regular(KeyPadMinus),
+            /* KEY_KPPLUSMINUS */ 118 => Regular(KeyPadPlusMinus),
+            /* KEY_PAUSE */ 119 => Regular(Pause),
             /* KEY_KPCOMMA */ 121 => Regular(KeyPadComma),
+            /* KEY_HANGEUL */ 122 => Regular(Lang1),
+            /* KEY_HANJA */ 123 => Regular(Lang2),
+            /* KEY_YEN */ 124 => Regular(International3),
             /* KEY_LEFTMETA */ 125 => Modifier(LeftSuper),
             /* KEY_RIGHTMETA */ 126 => Modifier(RightSuper),
+            /* KEY_COMPOSE */ 127 => Regular(Application),
+            /* KEY_STOP */ 128 => Consumer(BrowserStop),
+            /* KEY_AGAIN */ 129 => Regular(Again),
+            /* KEY_PROPS */ 130 => Regular(Menu),
+            /* KEY_UNDO */ 131 => Regular(Undo),
+            /* KEY_FRONT */ 132 => Regular(Select),
+            /* KEY_COPY */ 133 => Regular(Copy),
+            /* KEY_OPEN */ 134 => Regular(Execute),
+            /* KEY_PASTE */ 135 => Regular(Paste),
+            /* KEY_FIND */ 136 => Regular(Find),
+            /* KEY_CUT */ 137 => Regular(Cut),
+            /* KEY_HELP */ 138 => Regular(Help),
+            /* KEY_MENU */ 139 => Regular(Menu),
+            /* KEY_CALC */ 140 => Consumer(Calculator),
+            /* KEY_SLEEP */ 142 => System(Sleep),
+            /* KEY_WAKEUP */ 143 => System(WakeUp),
+            /* KEY_WWW */ 150 => Consumer(Browser),
+            /* KEY_MAIL */ 155 => Consumer(Mail),
+            /* KEY_BOOKMARKS */ 156 => Consumer(BrowserBookmarks),
+            /* KEY_COMPUTER */ 157 => Consumer(MyComputer),
+            /* KEY_BACK */ 158 => Consumer(BrowserBack),
+            /* KEY_FORWARD */ 159 => Consumer(BrowserForward),
+            /* KEY_EJECTCD */ 161 => Consumer(Eject),
+            /* KEY_NEXTSONG */ 163 => Consumer(NextTrack),
+            /* KEY_PLAYPAUSE */ 164 => Consumer(PlayPause),
+            /* KEY_PREVIOUSSONG */ 165 => Consumer(PreviousTrack),
+            /* KEY_STOPCD */ 166 => Consumer(MediaStop),
+            /* KEY_RECORD */ 167 => Consumer(Record),
+            /* KEY_REWIND */ 168 => Consumer(Rewind),
+            /* KEY_HOMEPAGE */ 172 => Consumer(BrowserHome),
+            /* KEY_REFRESH */ 173 => Consumer(BrowserRefresh),
             /* KEY_KPLEFTPAREN */ 179 => Regular(KeyPadLeftParen),
             /* KEY_KPRIGHTPAREN */ 180 => Regular(KeyPadRightParen),
+            /* KEY_F13 */ 183 => Regular(F13),
+            /* KEY_F14 */ 184 => Regular(F14),
+            /* KEY_F15 */ 185 => Regular(F15),
+            /* KEY_F16 */ 186 => Regular(F16),
+            /* KEY_F17 */ 187 => Regular(F17),
+            /* KEY_F18 */ 188 => Regular(F18),
+            /* KEY_F19 */ 189 => Regular(F19),
+            /* KEY_F20 */ 190 => Regular(F20),
+            /* KEY_F21 */ 191 => Regular(F21),
+            /* KEY_F22 */ 192 => Regular(F22),
+            /* KEY_F23 */ 193 => Regular(F23),
+            /* KEY_F24 */ 194 => Regular(F24),
+            /* KEY_PLAYCD */ 200 => Consumer(MediaPlay),
+            /* KEY_PAUSECD */ 201 => Consumer(MediaPause),
+            /* KEY_FASTFORWARD */ 208 => Consumer(FastForward),
+            /* KEY_SEARCH */ 217 => Consumer(BrowserSearch),
+            /* KEY_ALTERASE */ 222 => Regular(AlternateErase),
+            /* KEY_CANCEL */ 223 => Regular(Cancel),
+            /* KEY_BRIGHTNESSDOWN */ 224 => Consumer(BrightnessDown),
+            /* KEY_BRIGHTNESSUP */ 225 => Consumer(BrightnessUp),
+            /* KEY_MEDIA */ 226 => Consumer(MediaSelect),
+            /* KEY_CLEAR */ 355 => Regular(Clear),
             _ => Unknown,
         }
     }
 }
 
+/***** USB keycode to Linux /dev/input keycode lookup table *****/
+// The inverse of the table above. Keys Linux has no keycode for are None.
+impl RegularKey {
+    /// The evdev key for this regular key, if Linux has one
+    pub fn to_evdev(self) -> Option<Key> {
+        match self {
+            Empty => Some(Key::KEY_RESERVED),
+            A => Some(Key::KEY_A),
+            B => Some(Key::KEY_B),
+            C => Some(Key::KEY_C),
+            D => Some(Key::KEY_D),
+            E => Some(Key::KEY_E),
+            F => Some(Key::KEY_F),
+            G => Some(Key::KEY_G),
+            H => Some(Key::KEY_H),
+            I => Some(Key::KEY_I),
+            J => Some(Key::KEY_J),
+            K => Some(Key::KEY_K),
+            L => Some(Key::KEY_L),
+            M => Some(Key::KEY_M),
+            N => Some(Key::KEY_N),
+            O => Some(Key::KEY_O),
+            P => Some(Key::KEY_P),
+            Q => Some(Key::KEY_Q),
+            R => Some(Key::KEY_R),
+            S => Some(Key::KEY_S),
+            T => Some(Key::KEY_T),
+            U => Some(Key::KEY_U),
+            V => Some(Key::KEY_V),
+            W => Some(Key::KEY_W),
+            X => Some(Key::KEY_X),
+            Y => Some(Key::KEY_Y),
+            Z => Some(Key::KEY_Z),
+            Num1 => Some(Key::KEY_1),
+            Num2 => Some(Key::KEY_2),
+            Num3 => Some(Key::KEY_3),
+            Num4 => Some(Key::KEY_4),
+            Num5 => Some(Key::KEY_5),
+            Num6 => Some(Key::KEY_6),
+            Num7 => Some(Key::KEY_7),
+            Num8 => Some(Key::KEY_8),
+            Num9 => Some(Key::KEY_9),
+            Num0 => Some(Key::KEY_0),
+            Enter => Some(Key::KEY_ENTER),
+            Escape => Some(Key::KEY_ESC),
+            Backspace => Some(Key::KEY_BACKSPACE),
+            Tab => Some(Key::KEY_TAB),
+            Space => Some(Key::KEY_SPACE),
+            Minus => Some(Key::KEY_MINUS),
+            Equals => Some(Key::KEY_EQUAL),
+            LeftSquareBracket => Some(Key::KEY_LEFTBRACE),
+            RightSquareBracket => Some(Key::KEY_RIGHTBRACE),
+            BackSlash => Some(Key::KEY_BACKSLASH),
+            Semicolon => Some(Key::KEY_SEMICOLON),
+            SingleQuote => Some(Key::KEY_APOSTROPHE),
+            Grave => Some(Key::KEY_GRAVE),
+            Comma => Some(Key::KEY_COMMA),
+            Period => Some(Key::KEY_DOT),
+            ForwardSlash => Some(Key::KEY_SLASH),
+            CapsLock => Some(Key::KEY_CAPSLOCK),
+            F1 => Some(Key::KEY_F1),
+            F2 => Some(Key::KEY_F2),
+            F3 => Some(Key::KEY_F3),
+            F4 => Some(Key::KEY_F4),
+            F5 => Some(Key::KEY_F5),
+            F6 => Some(Key::KEY_F6),
+            F7 => Some(Key::KEY_F7),
+            F8 => Some(Key::KEY_F8),
+            F9 => Some(Key::KEY_F9),
+            F10 => Some(Key::KEY_F10),
+            F11 => Some(Key::KEY_F11),
+            F12 => Some(Key::KEY_F12),
+            PrintScreen => Some(Key::KEY_SYSRQ),
+            ScrollLock => Some(Key::KEY_SCROLLLOCK),
+            Pause => Some(Key::KEY_PAUSE),
+            Insert => Some(Key::KEY_INSERT),
+            Home => Some(Key::KEY_HOME),
+            PageUp => Some(Key::KEY_PAGEUP),
+            Delete => Some(Key::KEY_DELETE),
+            End => Some(Key::KEY_END),
+            PageDown => Some(Key::KEY_PAGEDOWN),
+            Right => Some(Key::KEY_RIGHT),
+            Left => Some(Key::KEY_LEFT),
+            Down => Some(Key::KEY_DOWN),
+            Up => Some(Key::KEY_UP),
+            NumLock => Some(Key::KEY_NUMLOCK),
+            KeyPadSlash => Some(Key::KEY_KPSLASH),
+            KeyPadAsterisk => Some(Key::KEY_KPASTERISK),
+            KeyPadMinus => Some(Key::KEY_KPMINUS),
+            KeyPadPlus => Some(Key::KEY_KPPLUS),
+            KeyPadEnter => Some(Key::KEY_KPENTER),
+            KeyPadNum1 => Some(Key::KEY_KP1),
+            KeyPadNum2 => Some(Key::KEY_KP2),
+            KeyPadNum3 => Some(Key::KEY_KP3),
+            KeyPadNum4 => Some(Key::KEY_KP4),
+            KeyPadNum5 => Some(Key::KEY_KP5),
+            KeyPadNum6 => Some(Key::KEY_KP6),
+            KeyPadNum7 => Some(Key::KEY_KP7),
+            KeyPadNum8 => Some(Key::KEY_KP8),
+            KeyPadNum9 => Some(Key::KEY_KP9),
+            KeyPadNum0 => Some(Key::KEY_KP0),
+            KeyPadPeriod => Some(Key::KEY_KPDOT),
+            NonUsBackslash => Some(Key::KEY_102ND),
+            Application => Some(Key::KEY_COMPOSE),
+            KeyPadEqual => Some(Key::KEY_KPEQUAL),
+            F13 => Some(Key::KEY_F13),
+            F14 => Some(Key::KEY_F14),
+            F15 => Some(Key::KEY_F15),
+            F16 => Some(Key::KEY_F16),
+            F17 => Some(Key::KEY_F17),
+            F18 => Some(Key::KEY_F18),
+            F19 => Some(Key::KEY_F19),
+            F20 => Some(Key::KEY_F20),
+            F21 => Some(Key::KEY_F21),
+            F22 => Some(Key::KEY_F22),
+            F23 => Some(Key::KEY_F23),
+            F24 => Some(Key::KEY_F24),
+            Execute => Some(Key::KEY_OPEN),
+            Help => Some(Key::KEY_HELP),
+            Menu => Some(Key::KEY_MENU),
+            Select => Some(Key::KEY_FRONT),
+            Again => Some(Key::KEY_AGAIN),
+            Undo => Some(Key::KEY_UNDO),
+            Cut => Some(Key::KEY_CUT),
+            Copy => Some(Key::KEY_COPY),
+            Paste => Some(Key::KEY_PASTE),
+            Find => Some(Key::KEY_FIND),
+            KeyPadComma => Some(Key::KEY_KPCOMMA),
+            International1 => Some(Key::KEY_RO),
+            International2 => Some(Key::KEY_KATAKANAHIRAGANA),
+            International3 => Some(Key::KEY_YEN),
+            International4 => Some(Key::KEY_HENKAN),
+            International5 => Some(Key::KEY_MUHENKAN),
+            International6 => Some(Key::KEY_KPJPCOMMA),
+            Lang1 => Some(Key::KEY_HANGEUL),
+            Lang2 => Some(Key::KEY_HANJA),
+            Lang3 => Some(Key::KEY_KATAKANA),
+            Lang4 => Some(Key::KEY_HIRAGANA),
+            Lang5 => Some(Key::KEY_ZENKAKUHANKAKU),
+            AlternateErase => Some(Key::KEY_ALTERASE),
+            Cancel => Some(Key::KEY_CANCEL),
+            Clear => Some(Key::KEY_CLEAR),
+            KeyPadLeftParen => Some(Key::KEY_KPLEFTPAREN),
+            KeyPadRightParen => Some(Key::KEY_KPRIGHTPAREN),
+            KeyPadPlusMinus => Some(Key::KEY_KPPLUSMINUS),
+            _ => None,
+        }
+    }
+}
+impl ModifierKey {
+    /// The evdev key for this modifier key, if Linux has one
+    pub fn to_evdev(self) -> Option<Key> {
+        match self {
+            LeftCtrl => Some(Key::KEY_LEFTCTRL),
+            LeftShift => Some(Key::KEY_LEFTSHIFT),
+            LeftAlt => Some(Key::KEY_LEFTALT),
+            LeftSuper => Some(Key::KEY_LEFTMETA),
+            RightCtrl => Some(Key::KEY_RIGHTCTRL),
+            RightShift => Some(Key::KEY_RIGHTSHIFT),
+            RightAlt => Some(Key::KEY_RIGHTALT),
+            RightSuper => Some(Key::KEY_RIGHTMETA),
+        }
+    }
+}
+impl ConsumerKey {
+    /// The evdev key for this consumer key, if Linux has one
+    pub fn to_evdev(self) -> Option<Key> {
+        match self {
+            BrightnessUp => Some(Key::KEY_BRIGHTNESSUP),
+            BrightnessDown => Some(Key::KEY_BRIGHTNESSDOWN),
+            MediaPlay => Some(Key::KEY_PLAYCD),
+            MediaPause => Some(Key::KEY_PAUSECD),
+            Record => Some(Key::KEY_RECORD),
+            FastForward => Some(Key::KEY_FASTFORWARD),
+            Rewind => Some(Key::KEY_REWIND),
+            NextTrack => Some(Key::KEY_NEXTSONG),
+            PreviousTrack => Some(Key::KEY_PREVIOUSSONG),
+            MediaStop => Some(Key::KEY_STOPCD),
+            Eject => Some(Key::KEY_EJECTCD),
+            PlayPause => Some(Key::KEY_PLAYPAUSE),
+            Mute => Some(Key::KEY_MUTE),
+            VolumeIncrement => Some(Key::KEY_VOLUMEUP),
+            VolumeDecrement => Some(Key::KEY_VOLUMEDOWN),
+            MediaSelect => Some(Key::KEY_MEDIA),
+            Mail => Some(Key::KEY_MAIL),
+            Calculator => Some(Key::KEY_CALC),
+            MyComputer => Some(Key::KEY_COMPUTER),
+            Browser => Some(Key::KEY_WWW),
+            BrowserSearch => Some(Key::KEY_SEARCH),
+            BrowserHome => Some(Key::KEY_HOMEPAGE),
+            BrowserBack => Some(Key::KEY_BACK),
+            BrowserForward => Some(Key::KEY_FORWARD),
+            BrowserStop => Some(Key::KEY_STOP),
+            BrowserRefresh => Some(Key::KEY_REFRESH),
+            BrowserBookmarks => Some(Key::KEY_BOOKMARKS),
+        }
+    }
+}
+impl SystemKey {
+    /// The evdev key for this system key, if Linux has one
+    pub fn to_evdev(self) -> Option<Key> {
+        match self {
+            PowerDown => Some(Key::KEY_POWER),
+            Sleep => Some(Key::KEY_SLEEP),
+            WakeUp => Some(Key::KEY_WAKEUP),
+        }
+    }
+}
+impl KeyCode {
+    /// The evdev key for this key, if Linux has one
+    pub fn to_evdev(self) -> Option<Key> {
+        match self {
+            Regular(regular_key) => regular_key.to_evdev(),
+            Modifier(modifier_key) => modifier_key.to_evdev(),
+            Consumer(consumer_key) => consumer_key.to_evdev(),
+            System(system_key) => system_key.to_evdev(),
+            Unknown => None,
+        }
+    }
+}
+/// Look a regular key up by its usage ID
+impl TryFrom<u8> for RegularKey {
+    type Error = Error;
+
+    fn try_from(usage: u8) -> Result<Self, Self::Error> {
+        RegularKey::ALL
+            .iter()
+            .find(|k| **k as u8 == usage)
+            .copied()
+            .ok_or_else(|| anyhow!("No regular key has usage ID {usage:#04x}"))
+    }
+}
+
 #[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "key-serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum KeyEvent {
     Release = 0x00,
     Press = 0x01,
     Repeat = 0x02,
 }
+
+/***** Tests *****/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev::EventType;
+    // Constants
+    /// KEY_MAX in linux/input-event-codes.h
+    const KEY_MAX: u16 = 0x2ff_u16;
+
+    fn from_evdev(key: Key) -> KeyCode {
+        InputEvent::new(EventType::KEY, key.code(), KeyEvent::Press as i32).into()
+    }
+
+    /// Both lookup tables agree, over every key and every evdev key
+    #[test]
+    fn evdev_round_trip() {
+        for key_code in KeyCode::all() {
+            if let Some(key) = key_code.to_evdev() {
+                assert_eq!(from_evdev(key), key_code, "{key_code:?} -> {key:?}");
+            }
+        }
+        for code in 0..=KEY_MAX {
+            let key_code = from_evdev(Key::new(code));
+            if key_code == Unknown {
+                continue;
+            }
+            let key = key_code
+                .to_evdev()
+                .unwrap_or_else(|| panic!("{code} -> {key_code:?} has no evdev key"));
+            assert_eq!(
+                from_evdev(key),
+                key_code,
+                "{code} -> {key_code:?} -> {key:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn friendly_names() {
+        for (name, key_code) in [
+            ("enter", Regular(Enter)),
+            ("~", Regular(Grave)),
+            ("lshift", Modifier(LeftShift)),
+            ("left-ctrl", Modifier(LeftCtrl)),
+            ("RightSuper", Modifier(RightSuper)),
+            ("kp+", Regular(KeyPadPlus)),
+            ("<BS>", Regular(Backspace)),
+            ("play-pause", Consumer(PlayPause)),
+        ] {
+            assert_eq!(name.parse::<KeyCode>().ok(), Some(key_code), "{name}");
+        }
+        for key_code in KeyCode::all() {
+            assert_eq!(key_code.to_string().parse::<KeyCode>().ok(), Some(key_code));
+        }
+    }
+
+    #[test]
+    fn sequence_notation() {
+        let quit_chord = "<Enter> ~ . <BS> <BS> <BS>".parse::<KeySequence>().unwrap();
+        assert_eq!(
+            *quit_chord,
+            [
+                Regular(Enter),
+                Modifier(LeftShift),
+                Regular(Grave),
+                Regular(Period),
+                Regular(Backspace),
+                Regular(Backspace),
+                Regular(Backspace),
+            ]
+        );
+        assert_eq!(quit_chord.to_string(), "<Enter> ~ . <BS> <BS> <BS>");
+        assert_eq!("<".parse::<KeySequence>().unwrap().to_string(), "<");
+        assert!("<NotAKey>".parse::<KeySequence>().is_err());
+        // Either side's modifier is only a chord pattern, never a key
+        assert!("<Ctrl> c".parse::<KeySequence>().is_err());
+    }
+
+    #[cfg(feature = "key-serde")]
+    #[test]
+    fn serde_names() {
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Recorded {
+            key: KeyCode,
+            event: KeyEvent,
+            chord: KeySequence,
+            keys: Vec<KeyCode>,
+        }
+        let recorded = Recorded {
+            key: Modifier(LeftShift),
+            event: KeyEvent::Press,
+            chord: "<Enter> ~ .".parse().unwrap(),
+            keys: vec![Regular(Enter), Consumer(PlayPause)],
+        };
+        let serialized = toml::to_string(&recorded).unwrap();
+        assert_eq!(
+            serialized,
+            "key = \"LShift\"\nevent = \"press\"\nchord = \"<Enter> ~ .\"\nkeys = [\"Enter\", \"PlayPause\"]\n"
+        );
+        assert_eq!(toml::from_str::<Recorded>(&serialized).unwrap(), recorded);
+        for key_code in KeyCode::all() {
+            let value = toml::Value::try_from(key_code).unwrap();
+            assert_eq!(value.try_into::<KeyCode>().unwrap(), key_code);
+        }
+    }
+
+    /// Every regular key is found by its usage ID, and only by it
+    #[test]
+    fn usage_round_trip() {
+        for key in RegularKey::ALL {
+            assert_eq!(RegularKey::try_from(*key as u8).ok(), Some(*key));
+        }
+        for usage in u8::MIN..=u8::MAX {
+            if let Ok(key) = RegularKey::try_from(usage) {
+                assert_eq!(key as u8, usage);
+            }
+        }
+    }
+}
@@ -4,244 +4,1039 @@
 **/
 
 /***** Setup *****/
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use chrono::Local;
 use env_logger::Builder;
-use evdev::{Device, EventStream, EventType, InputEvent};
+use evdev::{Device, EventStream, EventType, InputEvent, LedType, Synchronization};
 use log::{info, trace, warn};
-use std::{cell::Cell, fs::OpenOptions, io::Write, os::unix::prelude::OpenOptionsExt};
+use std::{
+    cell::Cell,
+    future,
+    io::{self, Write},
+    panic,
+    path::{Path, PathBuf},
+    task::Poll,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time::{sleep_until, Instant},
+};
 pub mod key;
 use key::*;
 pub mod chord;
+pub mod pattern;
 use chord::*;
-// Config constants
-const KEYBOARD_DEVICE_PATH: &str = "/dev/input/event5";
-const USB_GADGET_DEVICE_PATH: &str = "/dev/hidg0";
-// Constants
-const NO_BLOCK: i32 = 2048_i32;
-const MAX_ATTEMPTS: usize = 256_usize;
+pub mod config;
+use config::*;
+pub mod cli;
+pub mod discovery;
+pub mod gadget;
+pub mod hotplug;
+pub mod udc;
+use clap::Parser;
+use cli::*;
+use discovery::*;
+use gadget::*;
+use hotplug::*;
+use udc::*;
 
 /***** Enums *****/
+/// What an input event was to the keyboard
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ProcessedEvent {
+    /// A key changed (or repeated)
+    Key,
+    /// The end of a frame (SYN_REPORT): the keys pressed are consistent
+    EndOfFrame,
+    /// Anything else, or a key event discarded after SYN_DROPPED
+    Ignored,
+}
+
+/// A USB report and the gadget it's for
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum UsbReport {
+    /// For the keyboard gadget
+    Keyboard(Vec<u8>),
+    /// For the control gadget (media and system keys)
+    Control(Vec<u8>),
+}
 
 /***** Structs *****/
 /// USB key event
 struct USBKeyEvent<'b> {
     modifiers: &'b [ModifierKey],
     keys: &'b [RegularKey],
+    consumer_keys: &'b [ConsumerKey],
+    system_keys: &'b [SystemKey],
 }
 impl<'b> USBKeyEvent<'b> {
-    pub fn to_report(&self) -> [u8; 8] {
+    pub fn to_report(&self, report_mode: ReportMode) -> Vec<u8> {
+        let report = match report_mode {
+            ReportMode::Boot => self.to_boot_report(),
+            ReportMode::Nkro => self.to_nkro_report(),
+        };
+        trace!("USB report: {report:?}");
+        report
+    }
+
+    fn to_boot_report(&self) -> Vec<u8> {
         // [mod, <empty>, key 1, key n..., key 6]
-        let mut report = [0_u8; 8];
+        let mut report = vec![0_u8; BOOT_REPORT_LENGTH];
 
         // Modifier keys
         for modifier_key in self.modifiers {
             report[0] |= *modifier_key as u8;
         }
 
-        // Regular keys
+        // Regular keys. With too many, the host is told so rather than being
+        // sent some of them (HID 1.11, appendix C).
+        if self.is_rollover(ReportMode::Boot) {
+            report[2..].fill(ERROR_ROLL_OVER_USAGE);
+            return report;
+        }
         for (idx, key) in self.keys.iter().enumerate() {
-            if idx >= 6 {
-                warn!("6 keys pressed at once, some are getting dropped!");
-                break;
-            }
             report[2 + idx] = *key as u8;
         }
 
-        trace!("USB report: {report:?}");
         report
     }
+
+    /// The control gadget's reports, in the order of
+    /// `release_all_control_reports`
+    pub fn to_control_reports(&self) -> Vec<Vec<u8>> {
+        // [ID, usage (LE)]. Only the last media key pressed is sent.
+        let consumer_usage = self.consumer_keys.last().map_or(0_u16, |key| *key as u16);
+        let mut consumer_report = vec![CONSUMER_REPORT_ID];
+        consumer_report.extend(consumer_usage.to_le_bytes());
+        trace!("USB consumer report: {consumer_report:?}");
+
+        // [ID, power down | sleep << 1 | wake up << 2]
+        let mut system_report = vec![SYSTEM_REPORT_ID, 0_u8];
+        for system_key in self.system_keys {
+            system_report[1] |= 1 << (*system_key as u8 - SystemKey::PowerDown as u8);
+        }
+        trace!("USB system report: {system_report:?}");
+
+        vec![consumer_report, system_report]
+    }
+
+    /// Whether more keys are pressed than the report has room for
+    pub fn is_rollover(&self, report_mode: ReportMode) -> bool {
+        report_mode == ReportMode::Boot && self.keys.len() > BOOT_REPORT_KEY_COUNT
+    }
+
+    fn to_nkro_report(&self) -> Vec<u8> {
+        // [mod, <empty>, usages 0x00-0x07, usages 0x08-0x0F, ..., usages 0xD8-0xDF]
+        let mut report = vec![0_u8; NKRO_REPORT_LENGTH];
+
+        // Modifier keys
+        for modifier_key in self.modifiers {
+            report[0] |= *modifier_key as u8;
+        }
+
+        // Regular keys
+        for key in self.keys {
+            let usage = *key as usize;
+            report[2 + usage / 8] |= 1 << (usage % 8);
+        }
+
+        report
+    }
+}
+
+/// A grabbed evdev device feeding the keyboard
+struct InputDevice {
+    path: PathBuf,
+    name: String,
+    /// None for a device without events of its own (e.g. in tests)
+    event_stream: Option<EventStream>,
+    /// Keys held down on this device, for merging with the other devices
+    held: Vec<KeyCode>,
+    /// Events were dropped, so the device's events are discarded until the
+    /// end of the frame, then its keys are resynced
+    dropped: bool,
+}
+
+impl InputDevice {
+    /// Set the LEDs the device has from the HID LED bits, whose indices match
+    /// the evdev LED codes
+    fn set_leds(&mut self, leds: u8) {
+        let Some(event_stream) = &mut self.event_stream else {
+            return;
+        };
+        let device = event_stream.device_mut();
+        let Some(supported_leds) = device.supported_leds() else {
+            return;
+        };
+        let events = [
+            LedType::LED_NUML,
+            LedType::LED_CAPSL,
+            LedType::LED_SCROLLL,
+            LedType::LED_COMPOSE,
+            LedType::LED_KANA,
+        ]
+        .into_iter()
+        .filter(|led| supported_leds.contains(*led))
+        .map(|led| InputEvent::new(EventType::LED, led.0, ((leds >> led.0) & 1).into()))
+        .collect::<Vec<_>>();
+        if let Err(e) = device.send_events(&events) {
+            warn!("Failed to set LEDs of {}: {e}", self.path.display());
+        }
+    }
+
+    /// The keys the device holds down, read back from it
+    fn key_state(&self) -> io::Result<Vec<KeyCode>> {
+        let Some(event_stream) = &self.event_stream else {
+            return Err(io::ErrorKind::Unsupported.into());
+        };
+        let key_state = event_stream.device().get_key_state()?;
+        Ok(key_state
+            .iter()
+            .map(|key| InputEvent::new(EventType::KEY, key.code(), KeyEvent::Press as i32).into())
+            .filter(|key_code| *key_code != KeyCode::Unknown)
+            .collect())
+    }
 }
 
-/// Keyboard handler
-struct Keyboard<'a> {
-    event_stream: EventStream,
+/// Keyboard handler, merging every attached device into one keyboard
+struct Keyboard {
+    /// Empty while no keyboard is plugged in
+    devices: Vec<InputDevice>,
     keys: Vec<RegularKey>,
     modifiers: Vec<ModifierKey>,
+    consumer_keys: Vec<ConsumerKey>,
+    system_keys: Vec<SystemKey>,
     /// Sentinel value is KeyCode::Unknown
     chord_buffer: Cell<KeyCode>,
-    chord_length: u8,
-    possible_chords: Vec<&'a [KeyCode]>,
+    /// Follows the keys typed for chords
+    chord_matcher: ChordMatcher,
+    /// Keys to hold to quit, from the configuration
+    hold_to_quit: HoldToQuit,
+    /// Every combo looked for, from the configuration
+    combos: Vec<Combo>,
+    /// Holds the presses of keys that may be part of a combo
+    combo_matcher: ComboMatcher,
+    /// Whether chords and combos are looked for, rather than only keys
+    /// (e.g. not when monitoring)
+    pub chords_enabled: bool,
+    /// Whether key changes are withheld, as they may be part of a chord
+    /// consuming its keys
+    withholding: bool,
+    /// The key changes withheld from the host, in order
+    withheld: Vec<(KeyCode, KeyEvent)>,
+    /// Every chord listened for, from the configuration
+    chords: Vec<Chord>,
+    /// Chords without a profile or with this one are listened for
+    profile: String,
+    /// Set by the quit chord
+    quit_requested: bool,
+    /// Set by a chord to reload the configuration
+    reload_requested: bool,
+    /// Whether keys are sent to the host
+    passthrough: bool,
+    /// Reports to send before the next changed ones (e.g. typed text)
+    pending_reports: Vec<UsbReport>,
+    /// The host's LED state, from the lowest bit: num lock, caps lock,
+    /// scroll lock, compose, kana
+    leds: u8,
+    report_mode: ReportMode,
+    /// Whether system keys are kept from the host
+    block_system_keys: bool,
+    /// The last report returned, to only return reports that changed
+    last_report: Vec<u8>,
+    /// The last control gadget reports returned
+    last_control_reports: Vec<Vec<u8>>,
+    /// Whether the last report was a rollover error
+    rolled_over: bool,
+    /// How many times too many keys were pressed at once
+    rollover_count: u64,
 }
-impl<'a> Keyboard<'a> {
-    pub fn new(device_path: &str) -> Result<Self> {
-        let mut device = Device::open(device_path).context("Open device path")?;
-        device.grab().context("Grab device")?; // We are the only listener to the device events.
-        let event_stream = device.into_event_stream().context("Get event stream")?;
-        Ok(Self {
-            event_stream,
+impl Keyboard {
+    pub fn new(chord_config: &ChordConfig, gadget_config: &GadgetConfig) -> Self {
+        let mut keyboard = Self {
+            devices: Vec::new(),
             keys: Vec::new(),
             modifiers: Vec::new(),
-            possible_chords: Vec::new(),
-            chord_length: 0_u8,
+            consumer_keys: Vec::new(),
+            system_keys: Vec::new(),
+            chord_matcher: ChordMatcher::new(chord_config.start_key(), chord_config.timeout()),
+            chords: Vec::new(),
+            hold_to_quit: HoldToQuit::new(Vec::new(), Duration::ZERO),
+            combos: Vec::new(),
+            combo_matcher: ComboMatcher::new(chord_config.combo_window()),
+            chords_enabled: true,
+            withholding: false,
+            withheld: Vec::new(),
             chord_buffer: Cell::new(KeyCode::Unknown),
+            profile: DEFAULT_PROFILE.to_string(),
+            quit_requested: false,
+            reload_requested: false,
+            passthrough: true,
+            pending_reports: Vec::new(),
+            leds: 0_u8,
+            report_mode: gadget_config.report_mode,
+            block_system_keys: gadget_config.block_system_keys,
+            last_report: release_all_report(gadget_config.report_mode),
+            last_control_reports: release_all_control_reports(),
+            rolled_over: false,
+            rollover_count: 0_u64,
+        };
+        keyboard.set_chords(chord_config);
+        keyboard
+    }
+
+    pub fn is_attached(&self, device_path: &Path) -> bool {
+        self.devices.iter().any(|device| device.path == device_path)
+    }
+
+    /// Open a keyboard device and start reading from it alongside the others
+    pub fn attach(&mut self, device_path: &Path, grab: bool) -> Result<()> {
+        let mut device = Device::open(device_path).context("Open device path")?;
+        if grab {
+            device.grab().context("Grab device")?; // We are the only listener to the device events.
+        }
+        let name = device.name().unwrap_or("unnamed").to_string();
+        info!(
+            "Registered keyboard device {} ({name}).",
+            device_path.display()
+        );
+        let mut device = InputDevice {
+            path: device_path.to_path_buf(),
+            name,
+            event_stream: Some(device.into_event_stream().context("Get event stream")?),
+            held: Vec::new(),
+            dropped: false,
+        };
+        if grab {
+            device.set_leds(self.leds);
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    /// Show the host's LED state on every keyboard
+    pub fn set_leds(&mut self, leds: u8) {
+        trace!("Host LEDs: {leds:05b}");
+        self.leds = leds;
+        for device in &mut self.devices {
+            device.set_leds(leds);
+        }
+    }
+
+    /// Forget a keyboard device (e.g. it has been unplugged) and release the
+    /// keys held on it
+    pub fn detach(&mut self, device_idx: usize) {
+        let device = self.devices.remove(device_idx);
+        info!(
+            "Unregistered keyboard device {} ({}).",
+            device.path.display(),
+            device.name
+        );
+        for key_code in device.held {
+            if !self.is_held(key_code) {
+                self.release(key_code);
+            }
+        }
+        self.chord_buffer.set(KeyCode::Unknown);
+        self.chord_matcher.reset();
+        // Keys of a device that's gone are never replayed
+        self.combo_matcher.expire();
+        self.hold_to_quit.let_through();
+        self.withheld.clear();
+        self.withholding = false;
+    }
+
+    /// Listen for other chords, e.g. after reloading the configuration
+    pub fn set_chords(&mut self, chord_config: &ChordConfig) {
+        for held_key in (self.hold_to_quit.let_through())
+            .into_iter()
+            .chain(self.combo_matcher.expire())
+        {
+            self.press_through(held_key);
+        }
+        self.hold_to_quit = HoldToQuit::new(
+            chord_config.hold_to_quit.get_ref().0.clone(),
+            chord_config.hold_to_quit_duration(),
+        );
+        self.combos = chord_config.combos();
+        self.combo_matcher = ComboMatcher::new(chord_config.combo_window());
+        self.combo_matcher.set_combos(&self.combos, &self.profile);
+        self.chords = chord_config.chords();
+        self.chord_matcher = ChordMatcher::new(chord_config.start_key(), chord_config.timeout());
+        self.chord_matcher.set_chords(&self.chords, &self.profile);
+        self.withholding = false;
+        self.replay_withheld();
+    }
+
+    /// Start or stop sending keys to the host, releasing every key on the
+    /// host when stopping
+    pub fn set_passthrough(&mut self, passthrough: bool) {
+        info!(
+            "{} sending keys to the host.",
+            if passthrough { "Started" } else { "Stopped" }
+        );
+        if !passthrough {
+            self.queue_release_reports();
+        }
+        self.passthrough = passthrough;
+    }
+
+    /// Release every key on the host and forget the keys pressed, so they
+    /// stay released until pressed again
+    pub fn release_all(&mut self) {
+        self.keys.clear();
+        self.modifiers.clear();
+        self.consumer_keys.clear();
+        self.system_keys.clear();
+        self.queue_release_reports();
+    }
+
+    /// Forget the reports returned, as the host released every key (e.g. it
+    /// reconnected), so the keys pressed are returned again
+    pub fn resync_host(&mut self) {
+        self.last_report = release_all_report(self.report_mode);
+        self.last_control_reports = release_all_control_reports();
+    }
+
+    /// Queue the reports releasing every key, as the last ones sent
+    fn queue_release_reports(&mut self) {
+        self.last_report = release_all_report(self.report_mode);
+        self.last_control_reports = release_all_control_reports();
+        self.pending_reports
+            .push(UsbReport::Keyboard(self.last_report.clone()));
+        self.pending_reports.extend(
+            self.last_control_reports
+                .iter()
+                .cloned()
+                .map(UsbReport::Control),
+        );
+    }
+
+    /// Queue the reports typing text on the host, as with a US layout
+    pub fn type_text(&mut self, text: &str) {
+        if !self.passthrough {
+            return;
+        }
+        let mut reports = Vec::new();
+        for character in text.chars() {
+            let Some((key, shifted)) = RegularKey::from_typed_char(character) else {
+                warn!("Can't type {character:?}, skipping it.");
+                continue;
+            };
+            let modifiers = match shifted {
+                true => vec![ModifierKey::LeftShift],
+                false => Vec::new(),
+            };
+            let usb_key_event = USBKeyEvent {
+                modifiers: &modifiers,
+                keys: &[key],
+                consumer_keys: &[],
+                system_keys: &[],
+            };
+            reports.push(usb_key_event.to_report(self.report_mode));
+        }
+        self.queue_keyboard_reports(reports);
+    }
+
+    /// Queue the reports pressing keys together on the host, then releasing
+    /// them
+    pub fn tap_keys(&mut self, keys: &[KeyCode]) {
+        if !self.passthrough {
+            return;
+        }
+        let mut modifiers = Vec::new();
+        let mut regular_keys = Vec::new();
+        for key_code in keys {
+            match *key_code {
+                KeyCode::Modifier(modifier_key) => modifiers.push(modifier_key),
+                KeyCode::Regular(regular_key) => regular_keys.push(regular_key),
+                key_code => warn!("Can't tap {key_code}, skipping it."),
+            }
+        }
+        let usb_key_event = USBKeyEvent {
+            modifiers: &modifiers,
+            keys: &regular_keys,
+            consumer_keys: &[],
+            system_keys: &[],
+        };
+        let report = usb_key_event.to_report(self.report_mode);
+        self.queue_keyboard_reports(vec![report]);
+    }
+
+    /// Queue keyboard reports, each followed by one releasing every key. The
+    /// keys pressed so far (e.g. the chord's last key) are sent first. They
+    /// stay released afterwards until pressed again, so they're not typed
+    /// twice, but the modifiers held are pressed again.
+    fn queue_keyboard_reports(&mut self, reports: Vec<Vec<u8>>) {
+        let state_reports = self.state_reports();
+        self.pending_reports.extend(state_reports);
+        let release_report = release_all_report(self.report_mode);
+        for report in reports {
+            self.pending_reports.push(UsbReport::Keyboard(report));
+            self.pending_reports
+                .push(UsbReport::Keyboard(release_report.clone()));
+        }
+        self.last_report = release_report;
+        self.keys.clear();
+    }
+
+    /// Ungrab and forget every keyboard device
+    pub fn release_devices(&mut self) {
+        for device in self.devices.drain(..) {
+            let Some(mut event_stream) = device.event_stream else {
+                continue;
+            };
+            if let Err(e) = event_stream.device_mut().ungrab() {
+                warn!("Failed to ungrab {}: {e}", device.path.display());
+            }
+        }
+        self.keys.clear();
+        self.modifiers.clear();
+        self.consumer_keys.clear();
+        self.system_keys.clear();
+    }
+
+    /// Whether any device holds a key down
+    fn is_held(&self, key_code: KeyCode) -> bool {
+        self.devices
+            .iter()
+            .any(|device| device.held.contains(&key_code))
+    }
+
+    /// Whether every key to hold to quit is held
+    fn is_held_to_quit(&self) -> bool {
+        self.hold_to_quit.keys().iter().all(|pattern| {
+            (self.devices.iter())
+                .flat_map(|device| &device.held)
+                .any(|held_key| pattern.matches(*held_key))
         })
     }
 
-    /// Process key events and update the vecs holding what keys are pressed
-    pub fn process_key_events(&mut self, event: InputEvent, key_code: KeyCode) {
+    /// Quit once the keys to hold to quit have been held long enough, as of
+    /// an input event's timestamp (or now)
+    fn update_hold_to_quit(&mut self, timestamp: SystemTime) {
+        if !self.chords_enabled {
+            return;
+        }
+        let all_held = self.is_held_to_quit();
+        self.hold_to_quit.update(all_held, timestamp);
+        if self.hold_to_quit.is_due(timestamp) {
+            info!("Quit keys held.");
+            self.quit_requested = true;
+        }
+    }
+
+    /// Push key to vecs
+    fn press(&mut self, key_code: KeyCode) {
+        if let KeyCode::Regular(pressed_key) = key_code {
+            self.keys.push(pressed_key)
+        }
+        if let KeyCode::Modifier(pressed_key) = key_code {
+            self.modifiers.push(pressed_key)
+        }
+        if let KeyCode::Consumer(pressed_key) = key_code {
+            self.consumer_keys.push(pressed_key)
+        }
+        if let KeyCode::System(pressed_key) = key_code {
+            if self.block_system_keys {
+                info!("Blocked system key {pressed_key:?}.");
+                return;
+            }
+            self.system_keys.push(pressed_key)
+        }
+    }
+
+    /// Remove key from vecs
+    fn release(&mut self, key_code: KeyCode) {
+        if let KeyCode::Regular(released_key) = key_code {
+            if let Some(idx) = self.keys.iter().position(|k| k == &released_key) {
+                self.keys.remove(idx);
+            }
+        }
+        if let KeyCode::Modifier(released_key) = key_code {
+            if let Some(idx) = self.modifiers.iter().position(|k| k == &released_key) {
+                self.modifiers.remove(idx);
+            }
+        }
+        if let KeyCode::Consumer(released_key) = key_code {
+            if let Some(idx) = self.consumer_keys.iter().position(|k| k == &released_key) {
+                self.consumer_keys.remove(idx);
+            }
+        }
+        if let KeyCode::System(released_key) = key_code {
+            if let Some(idx) = self.system_keys.iter().position(|k| k == &released_key) {
+                self.system_keys.remove(idx);
+            }
+        }
+    }
+
+    /// Process key events and update the vecs holding what keys are pressed.
+    /// A key held on several devices is only released once all of them release it.
+    pub fn process_key_events(&mut self, device_idx: usize, event: InputEvent, key_code: KeyCode) {
         let key_event_enum_variant = event.value().try_into().unwrap_or(Release as u8);
+        let device = &mut self.devices[device_idx];
+        trace!("{}: {key_code:?} ({})", device.name, event.value());
         use KeyEvent::*;
         match key_event_enum_variant {
             // Released key
             _r if _r == Release as u8 => {
-                // Remove key from vecs
-                if let KeyCode::Regular(released_key) = key_code {
-                    if let Some(idx) = self.keys.iter().position(|k| k == &released_key) {
-                        self.keys.remove(idx);
-                    }
+                if let Some(idx) = device.held.iter().position(|k| k == &key_code) {
+                    device.held.remove(idx);
                 }
-                if let KeyCode::Modifier(released_key) = key_code {
-                    if let Some(idx) = self.modifiers.iter().position(|k| k == &released_key) {
-                        self.modifiers.remove(idx);
-                    }
-                }
-                // Remove key from chord buffer
-                self.chord_buffer.set(KeyCode::Unknown);
+                self.key_released(key_code);
             }
             // Pressed key
             _p if _p == Press as u8 => {
-                // Push key to vecs
-                if let KeyCode::Regular(pressed_key) = key_code {
-                    self.keys.push(pressed_key)
-                }
-                if let KeyCode::Modifier(pressed_key) = key_code {
-                    self.modifiers.push(pressed_key)
-                }
-                // Update chord buffer
-                self.chord_buffer.set(key_code);
+                let held_elsewhere = self.is_held(key_code);
+                self.devices[device_idx].held.push(key_code);
+                self.key_pressed(key_code, held_elsewhere);
             }
             // Repeated key
             _h if _h == Repeat as u8 => {
-                // Assume the press event already pushed the key into the vec
+                // Assume the press event already pushed the key into the vec.
+                // Repeats aren't typed again for chords.
+                self.chord_buffer.set(KeyCode::Unknown);
             }
             _ => unreachable!(),
         }
     }
 
-    /// Process any chords, doing the desired action
-    pub fn process_chords(&mut self) {
-        use KeyCode::*;
-        use ModifierKey::*;
-
-        // Listen for a chord
-        let chord_buffer = self.chord_buffer.get_mut();
-        if chord_buffer == &CHORD_SEQUENCE_START_KEY {
-            trace!("Chord sequence start key received. Listening for chords.");
-            self.possible_chords = ALL_CHORDS.to_vec();
-            self.chord_length = 1;
+    /// Apply a key press from any device. A key already held on another
+    /// device is only typed again for chords.
+    fn key_pressed(&mut self, key_code: KeyCode, held_elsewhere: bool) {
+        if !held_elsewhere && self.withhold_to_quit(key_code) {
+            self.chord_buffer.set(KeyCode::Unknown);
             return;
         }
-
-        if self.chord_length == 0 || chord_buffer == &mut Unknown {
+        if !held_elsewhere && self.hold_for_combo(key_code) {
+            // Chords don't see keys held for combos until let through
+            self.chord_buffer.set(KeyCode::Unknown);
             return;
         }
+        if !held_elsewhere {
+            self.release_or_withhold(key_code, KeyEvent::Press);
+        }
+        // Update chord buffer
+        self.chord_buffer.set(key_code);
+    }
+
+    /// Apply a key release from any device, once no device holds the key
+    fn key_released(&mut self, key_code: KeyCode) {
+        // Released before the hold completed, so it was only a press
+        if self.hold_to_quit.withheld() == Some(key_code) && !self.is_held(key_code) {
+            self.hold_to_quit.let_through();
+            self.press_through(key_code);
+        }
+        if self.chords_enabled {
+            // A combo's key released early wasn't pressed for the combo
+            for held_key in self.combo_matcher.release(key_code) {
+                self.press_through(held_key);
+            }
+        }
+        if !self.is_held(key_code) {
+            self.release_or_withhold(key_code, KeyEvent::Release);
+        }
+        // Remove key from chord buffer
+        self.chord_buffer.set(KeyCode::Unknown);
+    }
 
-        // Handle special chord keys
-        if let Some(replaced_modifier) = match chord_buffer {
-            Modifier(LeftCtrl) => Some(Modifier(EitherCtrl)),
-            Modifier(LeftShift) => Some(Modifier(EitherShift)),
-            Modifier(LeftAlt) => Some(Modifier(EitherAlt)),
-            Modifier(LeftSuper) => Some(Modifier(EitherSuper)),
-            Modifier(RightCtrl) => Some(Modifier(EitherCtrl)),
-            Modifier(RightShift) => Some(Modifier(EitherShift)),
-            Modifier(RightAlt) => Some(Modifier(EitherAlt)),
-            Modifier(RightSuper) => Some(Modifier(EitherSuper)),
-            _ => None,
-        } {
-            trace!("Chord modifier swapped with {replaced_modifier:?}");
-            *chord_buffer = replaced_modifier;
-        };
+    /// Apply a key change to the keys pressed, or keep it from the host while
+    /// a chord consuming its keys may be typed. Keys pressed before are
+    /// released as usual.
+    fn release_or_withhold(&mut self, key_code: KeyCode, key_event: KeyEvent) {
+        match key_event {
+            KeyEvent::Press if self.withholding => self.withheld.push((key_code, key_event)),
+            KeyEvent::Press => self.press(key_code),
+            _ if self.withheld.contains(&(key_code, KeyEvent::Press)) => {
+                self.withheld.push((key_code, key_event))
+            }
+            _ => self.release(key_code),
+        }
+    }
 
-        // Iterate through possible chords
-        self.possible_chords.retain(|chord| {
-            // Chords do not have CHORD_SEQUENCE_START_KEY as their first element,
-            // but it still is counted in self.chord_length
-            if let Some(next_key_of_this_chord) = chord.get(self.chord_length as usize - 1) {
-                if *chord_buffer == *next_key_of_this_chord {
-                    trace!("Positive match ({next_key_of_this_chord:?}) for {chord:?}");
-                    return true;
+    /// Keep a key press from the host while it completes the keys held to
+    /// quit. Another key pressed first lets it through, keeping the order.
+    /// Returns whether the press is withheld.
+    fn withhold_to_quit(&mut self, key_code: KeyCode) -> bool {
+        if !self.chords_enabled {
+            return false;
+        }
+        if let Some(withheld) = self.hold_to_quit.let_through() {
+            self.press_through(withheld);
+        }
+        let all_held = self.is_held_to_quit();
+        self.hold_to_quit.withhold(key_code, all_held)
+    }
+
+    /// Hold a key press while it may be part of a combo, doing the combo's
+    /// action once complete. Returns whether the press is held or suppressed.
+    fn hold_for_combo(&mut self, key_code: KeyCode) -> bool {
+        if !self.chords_enabled {
+            return false;
+        }
+        match self.combo_matcher.press(key_code) {
+            ComboPress::Held => true,
+            ComboPress::Fired(combo_idx) => {
+                let combo = &self.combos[combo_idx];
+                trace!("Combo fired: {:?}", combo.keys);
+                let action = combo.action.clone();
+                self.handle_chord(&action);
+                true
+            }
+            ComboPress::Pass(held_keys) if held_keys.is_empty() => false,
+            ComboPress::Pass(held_keys) => {
+                for held_key in held_keys {
+                    self.press_through(held_key);
                 }
-                trace!(
-                    "Negative match ({:?} vs. {next_key_of_this_chord:?}) for {chord:?}",
-                    *chord_buffer
-                );
-                return false;
-            }
-            trace!("Out of range for {chord:?}");
-            false
-        });
-        self.chord_length += 1;
-
-        // Check if we have concluded a chord. Assume all chords diverge at some point.
-        if self.possible_chords.is_empty() {
-            self.chord_length = 0;
-        }
-        if self.possible_chords.len() != 1 {
-            return;
+                // The key may start another combo
+                self.hold_for_combo(key_code)
+            }
         }
-        let chord = &self.possible_chords[0];
-        if chord.len() as u8 != self.chord_length {
+    }
+
+    /// Press a key that was held for a combo, as if it was just pressed. Its
+    /// report is queued, as the key may be released in the same frame.
+    fn press_through(&mut self, key_code: KeyCode) {
+        self.release_or_withhold(key_code, KeyEvent::Press);
+        if self.passthrough {
+            let reports = self.state_reports();
+            self.pending_reports.extend(reports);
+        }
+        self.feed_chord(key_code);
+    }
+
+    /// Process any chords, doing the desired action
+    pub fn process_chords(&mut self) {
+        let key_code = self.chord_buffer.get();
+        if key_code != KeyCode::Unknown {
+            self.feed_chord(key_code);
+        }
+    }
+
+    fn feed_chord(&mut self, key_code: KeyCode) {
+        if !self.chords_enabled {
             return;
         }
+        let fired_chord = self.chord_matcher.feed(key_code);
+        self.finish_chord_key(fired_chord, Some(key_code));
+    }
 
-        // See chord.rs
-        self.handle_chord(chord);
+    /// Swallow or replay the keys withheld, then do the chord's action
+    /// (see chord.rs). The key is the one just fed to the chord matcher.
+    fn finish_chord_key(&mut self, fired_chord: Option<FiredChord>, key_code: Option<KeyCode>) {
+        if let Some(fired_chord) = fired_chord {
+            let chord = &self.chords[fired_chord.chord_idx];
+            trace!("Chord fired: {:?}", chord.sequence);
+            if chord.consume {
+                // The key ending the chord isn't part of it
+                let ended_by = key_code.filter(|_| fired_chord.ended_by_key);
+                self.swallow_withheld(ended_by);
+            }
+        }
+        // The start key begins anew, so it's sent along with anything before it
+        let restarted = key_code == Some(self.chord_matcher.start_key());
+        if restarted || !self.chord_matcher.withholds() {
+            self.replay_withheld();
+        }
+        if let Some(fired_chord) = fired_chord {
+            let action = self.chords[fired_chord.chord_idx].action.clone();
+            self.handle_chord(&action);
+        }
+        self.withholding = self.chord_matcher.withholds();
+        if !self.withholding {
+            self.replay_withheld();
+        }
     }
 
-    /// Block to read events from the keyboard, process them, and then return a
-    /// USB key event.
-    pub async fn read_process(&mut self) -> Result<USBKeyEvent> {
-        // Read key events
-        let mut event;
-        loop {
-            event = self
-                .event_stream
-                .next_event()
-                .await
-                .context("Fetch next event of keyboard event stream")?;
-            if event.event_type() == EventType::KEY {
-                break;
-            } else if event.event_type() != EventType::SYNCHRONIZATION {
-                trace!("Skipped event type {:?} (not sync).", event.event_type());
+    /// Drop the key changes withheld for a chord that consumed them, except
+    /// the press of the key that ended the chord
+    fn swallow_withheld(&mut self, ended_by: Option<KeyCode>) {
+        let ending_press = ended_by.map(|key_code| (key_code, KeyEvent::Press));
+        let last = self.withheld.pop_if(|last| Some(*last) == ending_press);
+        trace!("Swallowed chord keys {:?}", self.withheld);
+        self.withheld.clear();
+        self.withheld.extend(last);
+    }
+
+    /// Apply the withheld key changes in order, queueing a report after each
+    /// so modifiers apply to the same keys as they did
+    fn replay_withheld(&mut self) {
+        for (key_code, key_event) in std::mem::take(&mut self.withheld) {
+            trace!("Replaying {key_code:?} ({key_event:?})");
+            match key_event {
+                KeyEvent::Press => self.press(key_code),
+                _ => self.release(key_code),
+            }
+            if self.passthrough {
+                let reports = self.state_reports();
+                self.pending_reports.extend(reports);
             }
         }
-        let key_code = event.into();
+    }
 
-        // Process
-        self.process_key_events(event, key_code);
-        self.process_chords();
+    /// Block until any device sends an event, returning the device's index.
+    /// Blocks forever while no keyboard is attached.
+    pub async fn next_event(&mut self) -> (usize, io::Result<InputEvent>) {
+        future::poll_fn(|cx| {
+            for (device_idx, device) in self.devices.iter_mut().enumerate() {
+                let Some(event_stream) = &mut device.event_stream else {
+                    continue;
+                };
+                if let Poll::Ready(event) = event_stream.poll_event(cx) {
+                    return Poll::Ready((device_idx, event));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Process any input event from a device. Key changes are applied as they
+    /// come, but the keys pressed are only consistent at the end of a frame.
+    pub fn process_event(&mut self, device_idx: usize, event: InputEvent) -> ProcessedEvent {
+        let device = &mut self.devices[device_idx];
+        match event.event_type() {
+            EventType::KEY if device.dropped => {
+                trace!("{}: Discarded {event:?} (events dropped)", device.name);
+                ProcessedEvent::Ignored
+            }
+            EventType::KEY => {
+                self.process_key_events(device_idx, event, event.into());
+                self.update_hold_to_quit(event.timestamp());
+                ProcessedEvent::Key
+            }
+            EventType::SYNCHRONIZATION if event.code() == Synchronization::SYN_DROPPED.0 => {
+                warn!("{}: Events dropped, resyncing keys.", device.name);
+                device.dropped = true;
+                ProcessedEvent::Ignored
+            }
+            EventType::SYNCHRONIZATION if event.code() == Synchronization::SYN_REPORT.0 => {
+                if device.dropped {
+                    device.dropped = false;
+                    match device.key_state() {
+                        Ok(pressed) => self.resync_keys(device_idx, &pressed),
+                        Err(e) => warn!("{}: Failed to get key state: {e}", device.name),
+                    }
+                }
+                ProcessedEvent::EndOfFrame
+            }
+            event_type => {
+                trace!("Skipped event type {event_type:?}.");
+                ProcessedEvent::Ignored
+            }
+        }
+    }
 
-        trace!("Keys pressed: {:?}", self.keys);
-        trace!("Modifiers pressed: {:?}", self.modifiers);
+    /// Press and release keys to match the keys a device holds down, after
+    /// events were dropped
+    fn resync_keys(&mut self, device_idx: usize, pressed: &[KeyCode]) {
+        let released = (self.devices[device_idx].held.iter())
+            .filter(|key_code| !pressed.contains(key_code))
+            .copied()
+            .collect::<Vec<_>>();
+        for key_code in released {
+            let event = InputEvent::new(EventType::KEY, 0, KeyEvent::Release as i32);
+            self.process_key_events(device_idx, event, key_code);
+        }
+        for key_code in pressed {
+            if !self.devices[device_idx].held.contains(key_code) {
+                let event = InputEvent::new(EventType::KEY, 0, KeyEvent::Press as i32);
+                self.process_key_events(device_idx, event, *key_code);
+            }
+        }
+    }
 
-        // Send the USB key event
-        Ok(USBKeyEvent {
+    /// The USB key event for the keys currently pressed
+    pub fn usb_key_event(&self) -> USBKeyEvent<'_> {
+        USBKeyEvent {
             keys: &self.keys,
             modifiers: &self.modifiers,
-        })
+            consumer_keys: &self.consumer_keys,
+            system_keys: &self.system_keys,
+        }
+    }
+
+    /// The queued reports, then the USB reports for the keys currently pressed
+    /// that differ from the last ones returned
+    pub fn changed_reports(&mut self) -> Vec<UsbReport> {
+        let mut reports = std::mem::take(&mut self.pending_reports);
+        if self.passthrough {
+            reports.extend(self.state_reports());
+        }
+        reports
+    }
+
+    /// The USB reports for the keys currently pressed that differ from the
+    /// last ones returned
+    fn state_reports(&mut self) -> Vec<UsbReport> {
+        let mut reports = Vec::new();
+        if let Some(report) = self.changed_keyboard_report() {
+            reports.push(UsbReport::Keyboard(report));
+        }
+        let control_reports = self.usb_key_event().to_control_reports();
+        for (report, last_report) in control_reports
+            .into_iter()
+            .zip(&mut self.last_control_reports)
+        {
+            if report != *last_report {
+                *last_report = report.clone();
+                reports.push(UsbReport::Control(report));
+            }
+        }
+        reports
+    }
+
+    /// The keyboard report for the keys currently pressed, if it differs from
+    /// the last one returned
+    fn changed_keyboard_report(&mut self) -> Option<Vec<u8>> {
+        let usb_key_event = self.usb_key_event();
+        let rolled_over = usb_key_event.is_rollover(self.report_mode);
+        let report = usb_key_event.to_report(self.report_mode);
+        if rolled_over && !self.rolled_over {
+            self.rollover_count += 1;
+            warn!(
+                "More than {BOOT_REPORT_KEY_COUNT} keys pressed at once, reporting rollover \
+                 ({} times so far).",
+                self.rollover_count
+            );
+        }
+        self.rolled_over = rolled_over;
+        if report == self.last_report {
+            return None;
+        }
+        self.last_report = report.clone();
+        Some(report)
+    }
+
+    /// Block to read events from the keyboard, process them, and then return
+    /// USB reports once a frame changed the keys pressed. If the keyboard goes
+    /// away, all keys are released.
+    pub async fn read_process(&mut self) -> Vec<UsbReport> {
+        loop {
+            // Read events, until the chord being typed or the combo being
+            // pressed times out, or the keys to hold to quit are held long enough
+            let hold_deadline = self.hold_to_quit.deadline().map(|deadline| {
+                let remaining = deadline.duration_since(SystemTime::now());
+                Instant::now() + remaining.unwrap_or_default()
+            });
+            let deadline = [
+                self.chord_matcher.deadline(),
+                self.combo_matcher.deadline(),
+                hold_deadline,
+            ];
+            let next_event = match deadline.into_iter().flatten().min() {
+                Some(deadline) => tokio::select! {
+                    next_event = self.next_event() => Some(next_event),
+                    _ = sleep_until(deadline) => None,
+                },
+                None => Some(self.next_event().await),
+            };
+            let Some((device_idx, event)) = next_event else {
+                self.update_hold_to_quit(SystemTime::now());
+                let now = Instant::now();
+                if self
+                    .combo_matcher
+                    .deadline()
+                    .is_some_and(|deadline| deadline <= now)
+                {
+                    for held_key in self.combo_matcher.expire() {
+                        self.press_through(held_key);
+                    }
+                }
+                if self
+                    .chord_matcher
+                    .deadline()
+                    .is_some_and(|deadline| deadline <= now)
+                {
+                    let fired_chord = self.chord_matcher.expire();
+                    self.finish_chord_key(fired_chord, None);
+                }
+                if self.quit_requested {
+                    return Vec::new();
+                }
+                let reports = self.changed_reports();
+                if !reports.is_empty() || self.reload_requested {
+                    return reports;
+                }
+                continue;
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    let device = &self.devices[device_idx];
+                    warn!(
+                        "Keyboard {} ({}) disconnected: {e}",
+                        device.path.display(),
+                        device.name
+                    );
+                    self.detach(device_idx);
+                    let reports = self.changed_reports();
+                    if !reports.is_empty() {
+                        return reports;
+                    }
+                    continue;
+                }
+            };
+
+            // Process
+            match self.process_event(device_idx, event) {
+                ProcessedEvent::Key => self.process_chords(),
+                ProcessedEvent::EndOfFrame => {}
+                ProcessedEvent::Ignored => continue,
+            }
+            if self.quit_requested {
+                return Vec::new();
+            }
+            if event.event_type() != EventType::SYNCHRONIZATION {
+                continue;
+            }
+
+            trace!("Keys pressed: {:?}", self.keys);
+            trace!("Modifiers pressed: {:?}", self.modifiers);
+            trace!("Consumer keys pressed: {:?}", self.consumer_keys);
+            trace!("System keys pressed: {:?}", self.system_keys);
+
+            // Send the USB reports if the frame changed anything, and let a
+            // reload be done
+            let reports = self.changed_reports();
+            if !reports.is_empty() || self.reload_requested {
+                return reports;
+            }
+        }
     }
 }
 
 /***** Auxiliary functions *****/
 
-/// Convert a chord sequence to a readable String
-fn chord_sequence_to_string(chord_sequence: &ChordSequence) -> String {
-    let mut ret = "Enter".to_string();
-    for key in chord_sequence {
-        ret.push_str(&match key {
-            KeyCode::Modifier(modifier_key) => format!(", {modifier_key:?}"),
-            KeyCode::Regular(regular_key) => format!(", {regular_key:?}"),
-            KeyCode::Unknown => ", UNKNOWN".into(),
-        });
-    }
-    ret
+/// Release all keys on the host when panicking, as the gadgets would
+/// otherwise keep the last reports latched
+fn set_release_all_panic_hook(gadgets: &[&Gadget]) {
+    let gadgets = gadgets
+        .iter()
+        .map(|gadget| {
+            (
+                gadget.path().to_path_buf(),
+                gadget.write_timeout(),
+                gadget.release_all_reports().to_vec(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        for (usb_gadget_device_path, write_timeout, reports) in &gadgets {
+            release_all_keys_blocking(usb_gadget_device_path, *write_timeout, reports);
+        }
+        default_hook(info);
+    }));
 }
 
 /***** Main *****/
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     // Setup logger
     Builder::new()
         .parse_default_env()
@@ -256,66 +1051,493 @@ async fn main() -> Result<()> {
         })
         .init();
 
+    // Load configuration
+    let mut config = Config::load(cli.config.as_deref()).context("Load configuration")?;
+
+    match cli.command.unwrap_or(Command::Run(RunArgs::default())) {
+        Command::Run(args) => {
+            args.apply(&mut config);
+            run(&config).await
+        }
+        Command::ListDevices => list_devices(&config),
+        Command::Monitor(args) => monitor(&config, &args).await,
+        Command::CheckConfig => check_config(&config),
+    }
+}
+
+/// Bridge the keyboard to the USB gadget until the quit chord is typed
+async fn run(config: &Config) -> Result<()> {
     println!(
         "USB Keyboard Bridge. To exit, type: {}",
-        chord_sequence_to_string(QUIT_CHORD_SEQUENCE)
+        config.chords.quit_chord()
     );
 
     // Setup keyboard
-    let mut keyboard = Keyboard::new(KEYBOARD_DEVICE_PATH)
-        .with_context(|| format!("Create keyboard at {KEYBOARD_DEVICE_PATH}"))?;
-    info!("Registered keyboard device.");
+    let mut device_watcher = DeviceWatcher::new().context("Watch for keyboards")?;
+    let mut keyboard = Keyboard::new(&config.chords, &config.gadget);
+    match find_keyboards_or_fail(config.keyboards.get_ref()) {
+        Ok(keyboard_device_paths) => {
+            // A device may be busy (e.g. grabbed by a remapper), which
+            // shouldn't keep the others from being bridged
+            for keyboard_device_path in keyboard_device_paths {
+                if let Err(e) = keyboard.attach(&keyboard_device_path, true) {
+                    warn!(
+                        "Failed to register keyboard {}: {e:#}",
+                        keyboard_device_path.display()
+                    );
+                }
+            }
+            if keyboard.devices.is_empty() {
+                bail!("Failed to register any of the keyboards found");
+            }
+        }
+        Err(e) => warn!("{e}. Waiting for a keyboard to be plugged in."),
+    }
     // Setup USB
-    let mut usb_gadget = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .custom_flags(NO_BLOCK)
-        .open(USB_GADGET_DEVICE_PATH)
-        .with_context(|| format!("Open USB gadget file at {USB_GADGET_DEVICE_PATH}"))?;
+    let mut gadget = Gadget::open(
+        &config.gadget.path,
+        &config.gadget,
+        vec![release_all_report(config.gadget.report_mode)],
+    )?;
     info!("Connected to USB gadget OTG device.");
-    let mut attempt;
+    let mut control_gadget = match Gadget::open(
+        &config.gadget.control_path,
+        &config.gadget,
+        release_all_control_reports(),
+    ) {
+        Ok(control_gadget) => Some(control_gadget),
+        Err(e) => {
+            warn!("{e:#}. Media and system keys are disabled (see enable-rpi-hid.sh).");
+            None
+        }
+    };
+    set_release_all_panic_hook(
+        &std::iter::once(&gadget)
+            .chain(control_gadget.as_ref())
+            .collect::<Vec<_>>(),
+    );
+
+    // Bridge until quitting, then make sure nothing stays pressed on the host
+    let mut udc_watcher = UdcWatcher::new(config.gadget.udc.as_deref());
+    let host_connected = udc_watcher.state().is_connected();
+    gadget.set_host_connected(host_connected).await;
+    if let Some(control_gadget) = &mut control_gadget {
+        control_gadget.set_host_connected(host_connected).await;
+    }
+    let result = bridge(
+        config,
+        &mut keyboard,
+        &mut device_watcher,
+        &mut udc_watcher,
+        &mut gadget,
+        &mut control_gadget,
+    )
+    .await;
+    info!("Shutting down.");
+    if keyboard.rollover_count > 0 {
+        info!(
+            "Too many keys were pressed at once {} times (see `gadget.report_mode`).",
+            keyboard.rollover_count
+        );
+    }
+    for gadget in std::iter::once(&mut gadget).chain(control_gadget.as_mut()) {
+        if !gadget.release_all().await {
+            warn!(
+                "Failed to release all keys (USB gadget is {:?}). Keys may be stuck on the host.",
+                gadget.health()
+            );
+        }
+    }
+    keyboard.release_devices();
+    result
+}
+
+/// Forward reports from the keyboard to the USB gadget until the quit chord
+/// is typed, a signal is received or an error occurs
+async fn bridge(
+    config: &Config,
+    keyboard: &mut Keyboard,
+    device_watcher: &mut DeviceWatcher,
+    udc_watcher: &mut UdcWatcher,
+    gadget: &mut Gadget,
+    control_gadget: &mut Option<Gadget>,
+) -> Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt()).context("Listen for SIGINT")?;
+    let mut terminate = signal(SignalKind::terminate()).context("Listen for SIGTERM")?;
     loop {
-        // Get USB report. The only time this should be okay to fail is when
-        // a keyboard is unplugged.
-        // TODO: Allow hot-swappable keyboards
-        let usb_key_event = keyboard
-            .read_process()
-            .await
-            .context("Reading and processing USB event from keyboard")?;
-        let usb_report = usb_key_event.to_report();
-        // Write in MAX_ATTEMPTS attempts. It appears that for whatever reason sometimes
-        // writing *always* fails with OS error 9, but doing it some arbitrary number of
-        // times (even if all those "fail") will have the characters sent out correctly.
-        // FIXME: This is pretty broken.
-        attempt = 0_usize;
-        loop {
-            attempt += 1;
-            trace!("Writing USB report, attempt {attempt}");
-            if usb_gadget
-                .write_all(&usb_report)
-                .map_err(|e| {
-                    warn!("Writing USB report {usb_report:?} on attempt {attempt} failed: {e}")
-                })
-                .is_ok()
-            {
-                break;
-            }
-            if attempt >= MAX_ATTEMPTS {
-                warn!("Failed to write USB report {MAX_ATTEMPTS} times.");
-            }
-        }
-        attempt = 0;
-        loop {
-            if usb_gadget
-                .flush()
-                .map_err(|e| warn!("Flushing USB gadget on attempt {attempt} failed: {e}"))
-                .is_ok()
-            {
-                break;
+        // Get USB reports, grabbing keyboards as they are plugged in. When a
+        // keyboard is unplugged, these are reports with its keys released.
+        let usb_reports = tokio::select! {
+            usb_reports = keyboard.read_process() => usb_reports,
+            device_path = device_watcher.next_device() => {
+                let device_path = device_path.context("Watch for keyboards")?;
+                if !keyboard.is_attached(&device_path)
+                    && path_matches(config.keyboards.get_ref(), &device_path)
+                {
+                    if let Err(e) = keyboard.attach(&device_path, true) {
+                        warn!("Failed to register keyboard {}: {e:#}", device_path.display());
+                    }
+                }
+                continue;
+            }
+            leds = gadget.read_leds() => {
+                keyboard.set_leds(leds);
+                continue;
+            }
+            // Reports whose write timed out, such as releases, aren't lost
+            _ = gadget.unsent_writable() => {
+                gadget.write_unsent().await;
+                continue;
+            }
+            _ = async {
+                match control_gadget {
+                    Some(control_gadget) => control_gadget.unsent_writable().await,
+                    None => future::pending().await,
+                }
+            } => {
+                if let Some(control_gadget) = control_gadget {
+                    control_gadget.write_unsent().await;
+                }
+                continue;
+            }
+            udc_state = udc_watcher.next_change() => {
+                gadget.set_host_connected(udc_state.is_connected()).await;
+                if let Some(control_gadget) = control_gadget {
+                    control_gadget.set_host_connected(udc_state.is_connected()).await;
+                }
+                if !udc_state.is_connected() {
+                    continue;
+                }
+                // The gadgets released every key, so press the keys held again
+                keyboard.resync_host();
+                keyboard.changed_reports()
+            }
+            _ = interrupt.recv() => {
+                info!("Received SIGINT.");
+                return Ok(());
+            }
+            _ = terminate.recv() => {
+                info!("Received SIGTERM.");
+                return Ok(());
+            }
+        };
+        for usb_report in usb_reports {
+            match (usb_report, &mut *control_gadget) {
+                (UsbReport::Keyboard(report), _) => gadget.send_report(&report).await,
+                (UsbReport::Control(report), Some(control_gadget)) => {
+                    control_gadget.send_report(&report).await
+                }
+                (UsbReport::Control(report), None) => {
+                    trace!("No control gadget, dropping report {report:?}")
+                }
+            }
+        }
+        if keyboard.quit_requested {
+            info!("Quit chord typed.");
+            return Ok(());
+        }
+        if keyboard.reload_requested {
+            keyboard.reload_requested = false;
+            match Config::load(config.path.as_deref()) {
+                Ok(new_config) => {
+                    keyboard.set_chords(&new_config.chords);
+                    info!("Reloaded the chords. Other settings take effect after a restart.");
+                }
+                Err(e) => warn!("Failed to reload the configuration: {e:#}"),
             }
-            if attempt >= MAX_ATTEMPTS {
-                warn!("Failed to flush USB report {MAX_ATTEMPTS} times.");
+        }
+    }
+}
+
+/***** Tests *****/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyCode::*;
+    use ModifierKey::*;
+    use RegularKey::*;
+
+    /// A keyboard with two devices without events of their own, fed by
+    /// `device_frame`
+    fn keyboard(source: &str) -> Keyboard {
+        let config = Config::parse(source, Path::new("test.toml")).unwrap();
+        let mut keyboard = Keyboard::new(&config.chords, &config.gadget);
+        for device_idx in 0..2 {
+            keyboard.devices.push(InputDevice {
+                path: PathBuf::from(format!("/dev/input/test{device_idx}")),
+                name: format!("Test keyboard {device_idx}"),
+                event_stream: None,
+                held: Vec::new(),
+                dropped: false,
+            });
+        }
+        keyboard
+    }
+
+    fn key(key_code: KeyCode, key_event: KeyEvent) -> InputEvent {
+        let code = key_code.to_evdev().unwrap().code();
+        InputEvent::new(EventType::KEY, code, key_event as i32)
+    }
+
+    fn sync(synchronization: Synchronization) -> InputEvent {
+        InputEvent::new(EventType::SYNCHRONIZATION, synchronization.0, 0)
+    }
+
+    /// Process a frame of events from a device as `read_process` does,
+    /// returning the reports sent
+    fn device_frame(
+        keyboard: &mut Keyboard,
+        device_idx: usize,
+        events: &[InputEvent],
+    ) -> Vec<UsbReport> {
+        for event in events
+            .iter()
+            .copied()
+            .chain([sync(Synchronization::SYN_REPORT)])
+        {
+            if keyboard.process_event(device_idx, event) == ProcessedEvent::Key {
+                keyboard.process_chords();
             }
         }
+        keyboard.changed_reports()
+    }
+
+    /// Press or release a key on the first device in a frame of its own,
+    /// returning the reports sent
+    fn frame(keyboard: &mut Keyboard, key_code: KeyCode, key_event: KeyEvent) -> Vec<UsbReport> {
+        device_frame(keyboard, 0, &[key(key_code, key_event)])
+    }
+
+    fn keyboard_report(modifiers: u8, keys: &[RegularKey]) -> UsbReport {
+        let mut report = vec![modifiers, 0, 0, 0, 0, 0, 0, 0];
+        for (idx, key) in keys.iter().enumerate() {
+            report[2 + idx] = *key as u8;
+        }
+        UsbReport::Keyboard(report)
+    }
+
+    /// A key held on two devices is only released once both let go
+    #[test]
+    fn merged_devices() {
+        use KeyEvent::*;
+        let mut keyboard = keyboard("");
+        let a_pressed = [keyboard_report(0, &[A])];
+        assert_eq!(
+            device_frame(&mut keyboard, 0, &[key(Regular(A), Press)]),
+            a_pressed
+        );
+        assert_eq!(
+            device_frame(&mut keyboard, 1, &[key(Regular(A), Press)]),
+            []
+        );
+        assert_eq!(
+            device_frame(&mut keyboard, 0, &[key(Regular(A), Release)]),
+            []
+        );
+        assert_eq!(
+            device_frame(&mut keyboard, 1, &[key(Regular(A), Release)]),
+            [keyboard_report(0, &[])]
+        );
+    }
+
+    /// Only the end of a frame sends a report, and only if the keys changed
+    #[test]
+    fn one_report_per_frame() {
+        use KeyEvent::*;
+        let mut keyboard = keyboard("");
+        assert_eq!(
+            device_frame(
+                &mut keyboard,
+                0,
+                &[key(Modifier(LeftShift), Press), key(Regular(A), Press)]
+            ),
+            [keyboard_report(0b00000010, &[A])]
+        );
+        assert_eq!(frame(&mut keyboard, Regular(A), Repeat), []);
+    }
+
+    /// Key events are discarded after SYN_DROPPED until the end of the frame,
+    /// then the keys are resynced with the device's
+    #[test]
+    fn resync_after_dropped_events() {
+        use KeyEvent::*;
+        let mut keyboard = keyboard("");
+        device_frame(
+            &mut keyboard,
+            0,
+            &[key(Regular(A), Press), key(Regular(B), Press)],
+        );
+        let dropped = sync(Synchronization::SYN_DROPPED);
+        assert_eq!(keyboard.process_event(0, dropped), ProcessedEvent::Ignored);
+        let event = key(Regular(A), Release);
+        assert_eq!(keyboard.process_event(0, event), ProcessedEvent::Ignored);
+        assert_eq!(device_frame(&mut keyboard, 0, &[]), []);
+        // As read back from the device
+        keyboard.resync_keys(0, &[Regular(B), Regular(C)]);
+        assert_eq!(keyboard.changed_reports(), [keyboard_report(0, &[B, C])]);
+        assert_eq!(keyboard.devices[0].held, [Regular(B), Regular(C)]);
+    }
+
+    /// The key completing the keys held to quit is kept from the host until
+    /// it's released early or another key is pressed
+    #[test]
+    fn hold_to_quit_withheld() {
+        use KeyEvent::*;
+        let mut keyboard = keyboard("[chords]\nhold_to_quit = \"<Ctrl> <Esc>\"\n");
+        let ctrl = 0b00000001;
+        assert_eq!(
+            frame(&mut keyboard, Modifier(RightCtrl), Press),
+            [keyboard_report(0b00010000, &[])]
+        );
+        assert_eq!(
+            frame(&mut keyboard, Modifier(RightCtrl), Release),
+            [keyboard_report(0, &[])]
+        );
+        frame(&mut keyboard, Modifier(LeftCtrl), Press);
+        assert_eq!(frame(&mut keyboard, Regular(Escape), Press), []);
+        assert_eq!(
+            frame(&mut keyboard, Regular(Escape), Release),
+            [keyboard_report(ctrl, &[Escape]), keyboard_report(ctrl, &[])]
+        );
+        assert_eq!(frame(&mut keyboard, Regular(Escape), Press), []);
+        assert_eq!(
+            frame(&mut keyboard, Regular(A), Press),
+            [
+                keyboard_report(ctrl, &[Escape]),
+                keyboard_report(ctrl, &[Escape, A])
+            ]
+        );
+        assert!(!keyboard.quit_requested);
+    }
+
+    /// A combo's key tapped on its own reaches the host
+    #[test]
+    fn combo_key_tapped() {
+        use KeyEvent::*;
+        let mut keyboard = keyboard("[[chords.combo]]\nkeys = \"j k\"\naction = \"quit\"\n");
+        assert_eq!(frame(&mut keyboard, Regular(J), Press), []);
+        assert_eq!(
+            frame(&mut keyboard, Regular(J), Release),
+            [keyboard_report(0, &[J]), keyboard_report(0, &[])]
+        );
+        assert_eq!(frame(&mut keyboard, Regular(K), Press), []);
+        assert_eq!(frame(&mut keyboard, Regular(J), Press), []);
+        assert!(keyboard.quit_requested);
+    }
+
+    /// The key completing a chord reaches the host before the text it types
+    #[test]
+    fn typed_text_after_chord_key() {
+        use KeyEvent::*;
+        let mut keyboard =
+            keyboard("[[chords.chord]]\nkeys = \"~ h\"\naction = { type = \"Hi\" }\n");
+        for (key_code, key_event) in [
+            (Regular(Enter), Press),
+            (Regular(Enter), Release),
+            (Modifier(LeftShift), Press),
+            (Regular(Grave), Press),
+            (Regular(Grave), Release),
+            (Modifier(LeftShift), Release),
+        ] {
+            frame(&mut keyboard, key_code, key_event);
+        }
+        assert_eq!(
+            frame(&mut keyboard, Regular(H), Press),
+            [
+                keyboard_report(0, &[H]),
+                keyboard_report(0b00000010, &[H]),
+                keyboard_report(0, &[]),
+                keyboard_report(0, &[I]),
+                keyboard_report(0, &[]),
+            ]
+        );
+        // The chord's key isn't pressed again
+        assert_eq!(frame(&mut keyboard, Regular(H), Release), []);
+    }
+
+    /// The keys held are sent again once the host released every key
+    #[test]
+    fn resync_host() {
+        let mut keyboard = keyboard("");
+        assert_eq!(
+            frame(&mut keyboard, Modifier(LeftCtrl), KeyEvent::Press),
+            [keyboard_report(0b00000001, &[])]
+        );
+        assert_eq!(keyboard.changed_reports(), []);
+        keyboard.resync_host();
+        assert_eq!(
+            keyboard.changed_reports(),
+            [keyboard_report(0b00000001, &[])]
+        );
+    }
+
+    fn usb_key_event<'b>(modifiers: &'b [ModifierKey], keys: &'b [RegularKey]) -> USBKeyEvent<'b> {
+        USBKeyEvent {
+            modifiers,
+            keys,
+            consumer_keys: &[],
+            system_keys: &[],
+        }
+    }
+
+    #[test]
+    fn boot_report() {
+        // [mod, <empty>, key 1, key n..., key 6]
+        let report = usb_key_event(&[LeftCtrl, RightShift], &[A, Enter]).to_boot_report();
+        assert_eq!(report, [0b00100001, 0, 0x04, 0x28, 0, 0, 0, 0]);
+
+        // Too many keys fill every slot with ErrorRollOver, the modifiers stay
+        let keys = [A, B, C, D, E, F, G];
+        let usb_key_event = usb_key_event(&[LeftShift], &keys);
+        assert!(usb_key_event.is_rollover(ReportMode::Boot));
+        assert!(!usb_key_event.is_rollover(ReportMode::Nkro));
+        assert_eq!(
+            usb_key_event.to_boot_report(),
+            [0b00000010, 0, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01]
+        );
+    }
+
+    #[test]
+    fn nkro_report() {
+        let report = usb_key_event(&[RightAlt], &[A, KeyPadHexadecimal]).to_nkro_report();
+        assert_eq!(report.len(), 30);
+        // Usage n is bit n % 8 of byte 2 + n / 8, after the reserved byte
+        let mut expected = vec![0_u8; 30];
+        expected[0] = 0b01000000;
+        expected[2] = 1 << 4; // A, 0x04
+        expected[29] = 1 << 5; // KeyPadHexadecimal, 0xDD
+        assert_eq!(report, expected);
+    }
+    #[test]
+    fn consumer_report() {
+        use ConsumerKey::*;
+        // [ID, usage (LE)], with only the last media key pressed
+        let media_keys = USBKeyEvent {
+            modifiers: &[],
+            keys: &[],
+            consumer_keys: &[Mute, BrowserBack],
+            system_keys: &[],
+        };
+        assert_eq!(
+            media_keys.to_control_reports(),
+            [vec![0x01, 0x24, 0x02], vec![0x02, 0]]
+        );
+        assert_eq!(
+            usb_key_event(&[], &[]).to_control_reports(),
+            release_all_control_reports()
+        );
+    }
+
+    #[test]
+    fn system_report() {
+        use SystemKey::*;
+        // [ID, power down | sleep << 1 | wake up << 2]
+        let system_keys = USBKeyEvent {
+            modifiers: &[],
+            keys: &[],
+            consumer_keys: &[],
+            system_keys: &[WakeUp, PowerDown],
+        };
+        assert_eq!(system_keys.to_control_reports()[1], [0x02, 0b00000101]);
     }
 }
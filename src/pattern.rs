@@ -0,0 +1,334 @@
+/*!
+ * Keyboard Bridge for Raspberry Pi - Key patterns
+ * Created by sheepy0125 on 2026-10-18 under the MIT license
+**/
+
+/***** Setup *****/
+use crate::key::*;
+use anyhow::Error;
+use std::{fmt, ops::Deref, str::FromStr};
+use KeyCode::*;
+use ModifierKey::*;
+use RegularKey::*;
+
+/***** Enums *****/
+/// A modifier on either side of the keyboard
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ModifierKind {
+    Ctrl,
+    Shift,
+    Alt,
+    Super,
+}
+/// A kind of key
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum KeyClass {
+    /// `A` to `Z`
+    Letter,
+    /// The number row's and the key pad's `0` to `9`
+    Digit,
+    /// `F1` to `F24`
+    Function,
+    /// Every key pad key
+    KeyPad,
+    /// Every modifier key
+    Modifier,
+    /// Every consumer key
+    Media,
+    /// Every key
+    Any,
+}
+/// What keys match a key typed in a chord, combo or held to quit. These only
+/// describe keys pressed on the keyboard; what's sent to the host is always a
+/// `KeyCode`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum KeyPattern {
+    /// Exactly this key
+    Key(KeyCode),
+    /// Either side's modifier (e.g. `<Ctrl>` for left or right control)
+    EitherSide(ModifierKind),
+    /// Any key of a class (e.g. `<Any-Digit>`)
+    Class(KeyClass),
+    /// Any key the pattern doesn't match (e.g. `<!Esc>`)
+    Not(Box<KeyPattern>),
+}
+
+/***** Matching *****/
+impl ModifierKind {
+    pub const ALL: &'static [ModifierKind] = &[Self::Ctrl, Self::Shift, Self::Alt, Self::Super];
+
+    /// Every name, the first being the one displayed
+    pub fn names(self) -> &'static [&'static str] {
+        match self {
+            Self::Ctrl => &["Ctrl", "Either-Ctrl"],
+            Self::Shift => &["Shift", "Either-Shift"],
+            Self::Alt => &["Alt", "Either-Alt"],
+            Self::Super => &["Super", "Either-Super", "Meta", "Gui", "Win"],
+        }
+    }
+
+    pub fn matches(self, modifier_key: ModifierKey) -> bool {
+        matches!(
+            (self, modifier_key),
+            (Self::Ctrl, LeftCtrl | RightCtrl)
+                | (Self::Shift, LeftShift | RightShift)
+                | (Self::Alt, LeftAlt | RightAlt)
+                | (Self::Super, LeftSuper | RightSuper)
+        )
+    }
+}
+impl KeyClass {
+    #[rustfmt::skip]
+    pub const ALL: &'static [KeyClass] = &[
+        Self::Letter, Self::Digit, Self::Function, Self::KeyPad, Self::Modifier, Self::Media,
+        Self::Any,
+    ];
+
+    /// Every name, the first being the one displayed
+    pub fn names(self) -> &'static [&'static str] {
+        match self {
+            Self::Letter => &["Any-Letter"],
+            Self::Digit => &["Any-Digit"],
+            Self::Function => &["Any-Function", "Any-F"],
+            Self::KeyPad => &["Any-KeyPad", "Any-KP"],
+            Self::Modifier => &["Any-Modifier", "Any-Mod"],
+            Self::Media => &["Any-Media"],
+            Self::Any => &["Any"],
+        }
+    }
+
+    pub fn matches(self, key_code: KeyCode) -> bool {
+        // Usage IDs, see `RegularKey`
+        let usage = match key_code {
+            Regular(regular_key) => Some(regular_key as u8),
+            _ => None,
+        };
+        match self {
+            Self::Letter => usage.is_some_and(|usage| (A as u8..=Z as u8).contains(&usage)),
+            Self::Digit => usage.is_some_and(|usage| {
+                (Num1 as u8..=Num0 as u8).contains(&usage)
+                    || (KeyPadNum1 as u8..=KeyPadNum0 as u8).contains(&usage)
+            }),
+            Self::Function => usage.is_some_and(|usage| {
+                (F1 as u8..=F12 as u8).contains(&usage) || (F13 as u8..=F24 as u8).contains(&usage)
+            }),
+            Self::KeyPad => usage.is_some_and(|usage| {
+                (KeyPadSlash as u8..=KeyPadPeriod as u8).contains(&usage)
+                    || [KeyPadEqual as u8, KeyPadComma as u8, KeyPadEqualSign as u8]
+                        .contains(&usage)
+                    || (KeyPadNum00 as u8..=KeyPadNum000 as u8).contains(&usage)
+                    || (KeyPadLeftParen as u8..=KeyPadHexadecimal as u8).contains(&usage)
+            }),
+            Self::Modifier => matches!(key_code, Modifier(_)),
+            Self::Media => matches!(key_code, Consumer(_)),
+            Self::Any => true,
+        }
+    }
+}
+impl KeyPattern {
+    pub fn matches(&self, key_code: KeyCode) -> bool {
+        match self {
+            Self::Key(key) => *key == key_code,
+            Self::EitherSide(kind) => {
+                matches!(key_code, Modifier(modifier_key) if kind.matches(modifier_key))
+            }
+            Self::Class(class) => class.matches(key_code),
+            Self::Not(pattern) => !pattern.matches(key_code),
+        }
+    }
+
+    /// Whether this pattern matches every key the other one does
+    pub fn includes(&self, other: &KeyPattern) -> bool {
+        KeyCode::all().all(|key_code| !other.matches(key_code) || self.matches(key_code))
+    }
+
+    /// The key matched, if it's the only one
+    pub fn key(&self) -> Option<KeyCode> {
+        match self {
+            Self::Key(key_code) => Some(*key_code),
+            _ => None,
+        }
+    }
+}
+impl From<KeyCode> for KeyPattern {
+    fn from(key_code: KeyCode) -> Self {
+        Self::Key(key_code)
+    }
+}
+
+/***** Pattern names *****/
+/// Parse a pattern from `!` and a pattern (negation), an either-side
+/// modifier's name (e.g. `Ctrl`), a class's name (e.g. `Any-Digit`) or
+/// otherwise a key (see `KeyCode`), ignoring case. The name may be in angle
+/// brackets as in sequence notation.
+impl FromStr for KeyPattern {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = unbracketed_name(name);
+        let is_named = |names: &[&str]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+        // A lone `!` is the key typing it
+        if let Some(negated) = name.strip_prefix('!').filter(|negated| !negated.is_empty()) {
+            return Ok(Self::Not(Box::new(negated.parse()?)));
+        }
+        if let Some(kind) = ModifierKind::ALL.iter().find(|kind| is_named(kind.names())) {
+            return Ok(Self::EitherSide(*kind));
+        }
+        if let Some(class) = KeyClass::ALL.iter().find(|class| is_named(class.names())) {
+            return Ok(Self::Class(*class));
+        }
+        Ok(Self::Key(name.parse()?))
+    }
+}
+impl fmt::Display for KeyPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(key_code) => write!(f, "{key_code}"),
+            Self::EitherSide(kind) => f.write_str(kind.names()[0]),
+            Self::Class(class) => f.write_str(class.names()[0]),
+            Self::Not(pattern) => write!(f, "!{pattern}"),
+        }
+    }
+}
+
+/***** Pattern sequences *****/
+/// Patterns in sequence notation (see `KeySequence`): a character stands for
+/// the key typing it (after either shift if it's shifted) and `<Name>` for
+/// any pattern by name, e.g. `<Enter> ~ . <BS> <BS> <BS>` or
+/// `<Ctrl> <Any-Digit>`
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PatternSequence(pub Vec<KeyPattern>);
+impl Deref for PatternSequence {
+    type Target = [KeyPattern];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl FromIterator<KeyPattern> for PatternSequence {
+    fn from_iter<T: IntoIterator<Item = KeyPattern>>(patterns: T) -> Self {
+        Self(patterns.into_iter().collect())
+    }
+}
+impl FromStr for PatternSequence {
+    type Err = Error;
+
+    fn from_str(notation: &str) -> Result<Self, Self::Err> {
+        parse_sequence(notation, KeyPattern::EitherSide(ModifierKind::Shift))
+    }
+}
+impl fmt::Display for PatternSequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shift = KeyPattern::EitherSide(ModifierKind::Shift);
+        fmt_sequence(f, self, &shift, |pattern| match pattern {
+            KeyPattern::Key(Regular(regular_key)) => Some(*regular_key),
+            _ => None,
+        })
+    }
+}
+
+/***** Serialization *****/
+#[cfg(feature = "key-serde")]
+mod pattern_serde {
+    use super::*;
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    /// By name
+    impl Serialize for KeyPattern {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+    impl<'de> Deserialize<'de> for KeyPattern {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let name = String::deserialize(deserializer)?;
+            name.parse().map_err(de::Error::custom)
+        }
+    }
+
+    /// In sequence notation
+    impl Serialize for PatternSequence {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+    /// From sequence notation or an array of pattern names
+    impl<'de> Deserialize<'de> for PatternSequence {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserialize_sequence::<_, _, KeyPattern>(
+                deserializer,
+                "a pattern sequence or an array of pattern names",
+            )
+        }
+    }
+}
+
+/***** Tests *****/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_names() {
+        for (name, pattern) in [
+            ("<Ctrl>", KeyPattern::EitherSide(ModifierKind::Ctrl)),
+            ("meta", KeyPattern::EitherSide(ModifierKind::Super)),
+            ("LCtrl", KeyPattern::Key(Modifier(LeftCtrl))),
+            ("any-digit", KeyPattern::Class(KeyClass::Digit)),
+            ("!Esc", KeyPattern::Not(Box::new(Regular(Escape).into()))),
+            ("!", KeyPattern::Key(Regular(Num1))),
+        ] {
+            assert_eq!(name.parse::<KeyPattern>().ok(), Some(pattern), "{name}");
+        }
+
+        let sequence = "<Enter> ~ <!Esc> <Any-Digit>"
+            .parse::<PatternSequence>()
+            .unwrap();
+        assert_eq!(
+            *sequence,
+            [
+                KeyPattern::Key(Regular(Enter)),
+                KeyPattern::EitherSide(ModifierKind::Shift),
+                KeyPattern::Key(Regular(Grave)),
+                KeyPattern::Not(Box::new(KeyPattern::Key(Regular(Escape)))),
+                KeyPattern::Class(KeyClass::Digit),
+            ]
+        );
+        assert_eq!(sequence.to_string(), "<Enter> ~ <!Esc> <Any-Digit>");
+        assert_eq!(
+            "<LShift> ` <Ctrl>"
+                .parse::<PatternSequence>()
+                .unwrap()
+                .to_string(),
+            "<LShift> ` <Ctrl>"
+        );
+    }
+
+    #[test]
+    fn matching() {
+        let pattern = |name: &str| name.parse::<KeyPattern>().unwrap();
+        assert!(pattern("Shift").matches(Modifier(RightShift)));
+        assert!(!pattern("Shift").matches(Modifier(RightCtrl)));
+        assert!(pattern("Any-Digit").matches(Regular(KeyPadNum0)));
+        assert!(!pattern("Any-Digit").matches(Regular(A)));
+        assert!(pattern("Any-Function").matches(Regular(F24)));
+        assert!(pattern("Any-KeyPad").matches(Regular(KeyPadHexadecimal)));
+        assert!(!pattern("Any-KeyPad").matches(Regular(DecimalSeparator)));
+        assert!(pattern("!Esc").matches(Regular(Enter)));
+        assert!(!pattern("!Esc").matches(Regular(Escape)));
+        assert!(!pattern("!Any").matches(Consumer(ConsumerKey::PlayPause)));
+    }
+    #[cfg(feature = "key-serde")]
+    #[test]
+    fn serde_names() {
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Recorded {
+            chord: PatternSequence,
+        }
+        let recorded = toml::from_str::<Recorded>("chord = [\"Ctrl\", \"!Esc\"]").unwrap();
+        assert_eq!(recorded.chord.to_string(), "<Ctrl> <!Esc>");
+        let serialized = toml::to_string(&recorded).unwrap();
+        assert_eq!(serialized, "chord = \"<Ctrl> <!Esc>\"\n");
+        assert_eq!(toml::from_str::<Recorded>(&serialized).unwrap(), recorded);
+    }
+}
@@ -0,0 +1,138 @@
+/*!
+ * Keyboard Bridge for Raspberry Pi - USB device controller state
+ * Created by sheepy0125 on 2026-10-18 under the MIT license
+**/
+
+/***** Setup *****/
+use log::{debug, info, warn};
+use std::{
+    fmt, fs, future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::time::{interval, Interval, MissedTickBehavior};
+// Constants
+const UDC_CLASS_PATH: &str = "/sys/class/udc";
+const POLL_INTERVAL: Duration = Duration::from_millis(250_u64);
+
+/***** Enums *****/
+/// The USB device controller's state, as in `/sys/class/udc/*/state`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum UdcState {
+    /// Enumerated by the host and ready for reports
+    Configured,
+    /// The host is asleep
+    Suspended,
+    /// No host at the other end of the cable
+    NotAttached,
+    /// Somewhere in between (attached, powered, default, addressed)
+    Other(String),
+    /// There is no UDC to watch, so the host is assumed to be there
+    Unknown,
+}
+impl UdcState {
+    fn parse(state: &str) -> Self {
+        match state.trim() {
+            "configured" => Self::Configured,
+            "suspended" => Self::Suspended,
+            "not attached" => Self::NotAttached,
+            other => Self::Other(other.to_string()),
+        }
+    }
+
+    /// Whether reports can reach the host
+    pub fn is_connected(&self) -> bool {
+        matches!(self, Self::Configured | Self::Unknown)
+    }
+}
+impl fmt::Display for UdcState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Configured => f.write_str("configured"),
+            Self::Suspended => f.write_str("suspended"),
+            Self::NotAttached => f.write_str("not attached"),
+            Self::Other(state) => f.write_str(state),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+/***** Structs *****/
+/// Polls the UDC's state in sysfs for the host connecting and disconnecting
+pub struct UdcWatcher {
+    /// None when there is no UDC
+    state_path: Option<PathBuf>,
+    state: UdcState,
+    interval: Interval,
+}
+impl UdcWatcher {
+    /// Watch the named UDC, or else the first one
+    pub fn new(udc: Option<&str>) -> Self {
+        let state_path = match udc {
+            Some(udc) => Some(Path::new(UDC_CLASS_PATH).join(udc).join("state")),
+            None => first_udc().map(|udc| udc.join("state")),
+        };
+        let state = match &state_path {
+            Some(state_path) => read_state(state_path),
+            None => {
+                warn!(
+                    "No USB device controller in {UDC_CLASS_PATH}, assuming the host is connected."
+                );
+                UdcState::Unknown
+            }
+        };
+        if let Some(state_path) = &state_path {
+            info!("USB host is {state} (from {}).", state_path.display());
+        }
+        let mut interval = interval(POLL_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            state_path,
+            state,
+            interval,
+        }
+    }
+
+    pub fn state(&self) -> &UdcState {
+        &self.state
+    }
+
+    /// Block until the state changes, returning the new state. Blocks forever
+    /// when there is no UDC.
+    pub async fn next_change(&mut self) -> UdcState {
+        let Some(state_path) = &self.state_path else {
+            return future::pending().await;
+        };
+        loop {
+            self.interval.tick().await;
+            let state = read_state(state_path);
+            if state != self.state {
+                info!("USB host is now {state} (was {}).", self.state);
+                self.state = state.clone();
+                return state;
+            }
+        }
+    }
+}
+
+/***** Auxiliary functions *****/
+
+/// The first UDC in sysfs, by name
+fn first_udc() -> Option<PathBuf> {
+    let mut udcs = fs::read_dir(UDC_CLASS_PATH)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect::<Vec<_>>();
+    udcs.sort();
+    udcs.into_iter().next()
+}
+
+fn read_state(state_path: &Path) -> UdcState {
+    match fs::read_to_string(state_path) {
+        Ok(state) => UdcState::parse(&state),
+        Err(e) => {
+            debug!("Reading {} failed: {e}", state_path.display());
+            UdcState::Unknown
+        }
+    }
+}